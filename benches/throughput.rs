@@ -0,0 +1,88 @@
+use std::io::Cursor;
+use std::sync::Arc;
+
+use criterion::{criterion_group, criterion_main, BenchmarkId, Criterion, Throughput};
+
+use av_data::packet::Packet;
+use av_format::buffer::AccReader;
+use av_format::demuxer::{Context as DemuxerContext, Event};
+use av_format::muxer::{Context as MuxerContext, Writer};
+
+use av_ivf::demuxer::{IvfDemuxer, IVF_DESC};
+use av_ivf::muxer::IvfMuxer;
+
+#[path = "../tests/common/mod.rs"]
+mod common;
+
+const AV1: &[u8] = include_bytes!("../assets/single_stream_av1.ivf");
+
+fn fixtures() -> Vec<(&'static str, Vec<u8>)> {
+    vec![("av1", AV1.to_vec()), ("vp8", common::vp8(300, 4096))]
+}
+
+fn demux(data: &[u8]) -> Vec<Packet> {
+    let mut demuxer = DemuxerContext::new(IvfDemuxer::new(), AccReader::new(Cursor::new(data)));
+    demuxer.read_headers().unwrap();
+
+    let mut packets = Vec::new();
+    while let Event::NewPacket(pkt) = demuxer.read_event().unwrap() {
+        packets.push(pkt);
+    }
+    packets
+}
+
+fn mux(packets: &[Arc<Packet>], write_buffer: usize) -> usize {
+    let mut ivf_muxer = IvfMuxer::new();
+    ivf_muxer.set_write_buffer(write_buffer);
+    let mut muxer = MuxerContext::new(ivf_muxer, Writer::new(Cursor::new(Vec::new())));
+    muxer.configure().unwrap();
+    muxer.write_header().unwrap();
+    for pkt in packets {
+        muxer.write_packet(pkt.clone()).unwrap();
+    }
+    muxer.write_trailer().unwrap();
+    muxer.writer().as_ref().1
+}
+
+fn bench_demux(c: &mut Criterion) {
+    let mut group = c.benchmark_group("demux");
+    for (name, data) in fixtures() {
+        group.throughput(Throughput::Bytes(data.len() as u64));
+        group.bench_with_input(BenchmarkId::from_parameter(name), &data, |b, data| {
+            b.iter(|| demux(data))
+        });
+    }
+    group.finish();
+}
+
+fn bench_mux(c: &mut Criterion) {
+    let mut group = c.benchmark_group("mux");
+    for (name, data) in fixtures() {
+        let packets = demux(&data).into_iter().map(Arc::new).collect::<Vec<_>>();
+        group.throughput(Throughput::Bytes(data.len() as u64));
+        group.bench_with_input(
+            BenchmarkId::new("unbuffered", name),
+            &packets,
+            |b, packets| b.iter(|| mux(packets, 0)),
+        );
+        group.bench_with_input(
+            BenchmarkId::new("buffered", name),
+            &packets,
+            |b, packets| b.iter(|| mux(packets, 64 * 1024)),
+        );
+    }
+    group.finish();
+}
+
+fn bench_probe(c: &mut Criterion) {
+    let mut group = c.benchmark_group("probe");
+    for (name, data) in fixtures() {
+        group.bench_with_input(BenchmarkId::from_parameter(name), &data, |b, data| {
+            b.iter(|| IVF_DESC.probe(data))
+        });
+    }
+    group.finish();
+}
+
+criterion_group!(benches, bench_demux, bench_mux, bench_probe);
+criterion_main!(benches);
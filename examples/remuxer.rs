@@ -1,5 +1,6 @@
 use std::fs::File;
 use std::io::{Cursor, Write};
+use std::ops::ControlFlow;
 use std::path::PathBuf;
 use std::sync::Arc;
 
@@ -8,7 +9,6 @@ use log::{debug, trace};
 
 use av_format::buffer::AccReader;
 use av_format::demuxer::Context as DemuxerContext;
-use av_format::demuxer::Event;
 use av_format::muxer::{Context as MuxerContext, Writer};
 
 use av_ivf::demuxer::*;
@@ -47,21 +47,17 @@ fn main() {
 
     loop {
         match demuxer.read_event() {
-            Ok(event) => match event {
-                Event::MoreDataNeeded(sz) => panic!("we needed more data: {} bytes", sz),
-                Event::NewStream(s) => panic!("new stream :{:?}", s),
-                Event::NewPacket(packet) => {
+            Ok(event) => {
+                let flow = handle_event(event, |packet| {
                     debug!("received packet with pos: {:?}", packet.pos);
                     muxer.write_packet(Arc::new(packet)).unwrap();
-                }
-                Event::Continue => continue,
-                Event::Eof => {
+                });
+                if let ControlFlow::Break(()) = flow {
                     muxer.write_trailer().unwrap();
                     debug!("EOF!");
                     break;
                 }
-                _ => unimplemented!(),
-            },
+            }
             Err(e) => {
                 debug!("error: {:?}", e);
                 break;
@@ -0,0 +1,68 @@
+use std::io;
+use std::ops::ControlFlow;
+use std::path::PathBuf;
+use std::sync::Arc;
+
+use clap::Parser;
+use log::{debug, trace};
+
+use av_format::buffer::AccReader;
+use av_format::demuxer::Context as DemuxerContext;
+use av_format::muxer::{Context as MuxerContext, Writer};
+
+use av_ivf::demuxer::*;
+use av_ivf::muxer::*;
+
+#[derive(Parser, Debug)]
+#[clap(name = "ivf to stdout")]
+/// Remux an ivf file to the standard output, e.g. to pipe it to a player
+struct Opts {
+    /// Input file
+    #[clap(short = 'i', value_parser)]
+    input: PathBuf,
+    /// Frame count to declare in the header, the input one by default
+    #[clap(short = 'n', value_parser)]
+    frames: Option<u32>,
+}
+
+fn main() {
+    let _ = pretty_env_logger::try_init();
+    let opts = Opts::parse();
+
+    let input = std::fs::File::open(opts.input).unwrap();
+    let acc = AccReader::new(input);
+    let mut demuxer = DemuxerContext::new(IvfDemuxer::new(), acc);
+
+    demuxer.read_headers().unwrap();
+    trace!("global info: {:#?}", demuxer.info);
+
+    // stdout is not seekable, the header is written once and never patched
+    let mut muxer = MuxerContext::new(IvfMuxer::new(), Writer::new(io::stdout()));
+
+    muxer.set_global_info(demuxer.info.clone()).unwrap();
+    muxer.configure().unwrap();
+    if let Some(frames) = opts.frames {
+        muxer.set_option("duration", frames as u64).unwrap();
+    }
+    muxer.write_header().unwrap();
+
+    loop {
+        match demuxer.read_event() {
+            Ok(event) => {
+                let flow = handle_event(event, |packet| {
+                    debug!("received packet with pos: {:?}", packet.pos);
+                    muxer.write_packet(Arc::new(packet)).unwrap();
+                });
+                if let ControlFlow::Break(()) = flow {
+                    muxer.write_trailer().unwrap();
+                    debug!("EOF!");
+                    break;
+                }
+            }
+            Err(e) => {
+                debug!("error: {:?}", e);
+                break;
+            }
+        }
+    }
+}
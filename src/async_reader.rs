@@ -0,0 +1,209 @@
+//!
+//! Asynchronous counterpart of `IvfFrameReader`, exposed as a
+//! `futures::Stream` of frames.
+//!
+//! Requires the `futures` feature.
+//!
+
+use std::io;
+use std::pin::Pin;
+use std::task::{Context, Poll};
+
+use futures_core::Stream;
+use futures_io::AsyncRead;
+use log::debug;
+
+use av_bitstream::byteread::*;
+
+use crate::common::{IVF_FRAME_HEADER_LEN, IVF_RESERVED_OFFSET};
+use crate::demuxer::{header_size, ivf_header, IvfFrame, IvfHeader};
+use crate::error::IvfError;
+use crate::index::{is_trailer, trailer_len};
+
+/// Largest read issued to the source at once
+const READ_CHUNK: usize = 1 << 20;
+
+/// Streams the frames of an IVF file from an `AsyncRead` source.
+///
+/// The header is parsed while polling for the first frame, partial reads
+/// are buffered until a whole frame is available.
+pub struct AsyncIvfFrameReader<R> {
+    inner: R,
+    header: Option<IvfHeader>,
+    buf: Vec<u8>,
+    filled: usize,
+    done: bool,
+}
+
+impl<R: AsyncRead + Unpin> AsyncIvfFrameReader<R> {
+    /// Creates a new reader, no data is read until the stream is polled.
+    pub fn new(inner: R) -> Self {
+        AsyncIvfFrameReader {
+            inner,
+            header: None,
+            buf: Vec::new(),
+            filled: 0,
+            done: false,
+        }
+    }
+
+    /// Returns the IVF header, once it has been read.
+    pub fn header(&self) -> Option<&IvfHeader> {
+        self.header.as_ref()
+    }
+
+    /// Fills the buffer up to `len` bytes.
+    ///
+    /// Resolves to `false` if the source ends before any byte is read.
+    ///
+    /// The buffer grows by at most `READ_CHUNK` bytes past the data read,
+    /// so a corrupted frame size cannot make it allocate more than the
+    /// stream holds.
+    fn poll_fill(&mut self, cx: &mut Context<'_>, len: usize) -> Poll<io::Result<bool>> {
+        while self.filled < len {
+            let start = self.filled;
+            let end = len.min(start + READ_CHUNK);
+            if self.buf.len() < end {
+                self.buf.resize(end, 0);
+            }
+            match Pin::new(&mut self.inner).poll_read(cx, &mut self.buf[start..end]) {
+                Poll::Ready(Ok(0)) if start == 0 => return Poll::Ready(Ok(false)),
+                Poll::Ready(Ok(0)) => return Poll::Ready(Err(io::ErrorKind::UnexpectedEof.into())),
+                Poll::Ready(Ok(n)) => self.filled += n,
+                Poll::Ready(Err(e)) => return Poll::Ready(Err(e)),
+                Poll::Pending => return Poll::Pending,
+            }
+        }
+        Poll::Ready(Ok(true))
+    }
+
+    fn poll_frame(&mut self, cx: &mut Context<'_>) -> Poll<io::Result<Option<IvfFrame>>> {
+        if self.header.is_none() {
+            if !futures_core::ready!(self.poll_fill(cx, IVF_RESERVED_OFFSET))? {
+                return Poll::Ready(Err(IvfError::TruncatedHeader.into()));
+            }
+            let size = header_size(get_u16l(&self.buf[6..8]));
+            futures_core::ready!(self.poll_fill(cx, size))?;
+            match ivf_header(&self.buf[..size]) {
+                Ok((_, header)) => self.header = Some(header),
+                Err(e) => {
+                    debug!("error reading header: {:?}", e);
+                    return Poll::Ready(Err(IvfError::InvalidHeader.into()));
+                }
+            }
+            self.buf.clear();
+            self.filled = 0;
+        }
+
+        if !futures_core::ready!(self.poll_fill(cx, IVF_FRAME_HEADER_LEN))? {
+            return Poll::Ready(Ok(None));
+        }
+        let size = get_u32l(&self.buf[0..4]);
+        let frame_len = IVF_FRAME_HEADER_LEN + size as usize;
+
+        // the index trailer is not a frame, once it validates in full
+        match trailer_len(&self.buf[..IVF_FRAME_HEADER_LEN]) {
+            Some(len) if len <= frame_len as u64 => {
+                futures_core::ready!(self.poll_fill(cx, len as usize))?;
+                if is_trailer(&self.buf[..len as usize]) {
+                    return Poll::Ready(Ok(None));
+                }
+            }
+            _ => {}
+        }
+        futures_core::ready!(self.poll_fill(cx, frame_len))?;
+
+        let timestamp = get_u64l(&self.buf[4..12]);
+        self.buf.truncate(frame_len);
+        let data = self.buf.split_off(IVF_FRAME_HEADER_LEN);
+        self.filled = 0;
+
+        Poll::Ready(Ok(Some(IvfFrame {
+            size,
+            timestamp,
+            data,
+        })))
+    }
+}
+
+impl<R: AsyncRead + Unpin> Stream for AsyncIvfFrameReader<R> {
+    type Item = io::Result<IvfFrame>;
+
+    fn poll_next(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<Self::Item>> {
+        let this = self.get_mut();
+        if this.done {
+            return Poll::Ready(None);
+        }
+
+        let res = futures_core::ready!(this.poll_frame(cx));
+        if !matches!(res, Ok(Some(_))) {
+            this.done = true;
+        }
+        Poll::Ready(res.transpose())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use futures_executor::block_on;
+    use futures_util::TryStreamExt;
+
+    use super::*;
+    use crate::reader::IvfFrameReader;
+
+    const IVF: &[u8] = include_bytes!("../assets/single_stream_av1.ivf");
+
+    /// Hands out a single byte per read, alternating with `Pending`
+    struct Trickle<'a> {
+        data: &'a [u8],
+        ready: bool,
+    }
+
+    impl AsyncRead for Trickle<'_> {
+        fn poll_read(
+            mut self: Pin<&mut Self>,
+            cx: &mut Context<'_>,
+            buf: &mut [u8],
+        ) -> Poll<io::Result<usize>> {
+            self.ready = !self.ready;
+            if !self.ready {
+                cx.waker().wake_by_ref();
+                return Poll::Pending;
+            }
+            match self.data.split_first() {
+                Some((&byte, rest)) => {
+                    buf[0] = byte;
+                    self.data = rest;
+                    Poll::Ready(Ok(1))
+                }
+                None => Poll::Ready(Ok(0)),
+            }
+        }
+    }
+
+    #[test]
+    fn byte_by_byte() {
+        let small = crate::test_util::ivf(b"AV01", &[(0, &[0x12, 0x00]), (1, &[]), (2, &[0x12])]);
+
+        for data in [IVF, &small] {
+            let reader = AsyncIvfFrameReader::new(Trickle { data, ready: false });
+
+            let frames: Vec<_> = block_on(reader.try_collect()).unwrap();
+            let expected = IvfFrameReader::new(data).unwrap().collect_frames().unwrap();
+
+            assert_eq!(frames, expected);
+        }
+    }
+
+    #[test]
+    fn oversized_frame() {
+        let mut data = crate::test_util::header(b"VP80", 1);
+        data.extend_from_slice(&u32::MAX.to_le_bytes());
+        data.extend_from_slice(&[0; 8]);
+        let mut reader = AsyncIvfFrameReader::new(data.as_slice());
+
+        let err = block_on(reader.try_next()).unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::UnexpectedEof);
+        assert!(reader.buf.capacity() <= IVF_FRAME_HEADER_LEN + READ_CHUNK);
+    }
+}
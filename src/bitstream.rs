@@ -0,0 +1,473 @@
+//!
+//! Lightweight, codec specific inspection of frame payloads.
+//!
+//! Only the first few bytes of a payload are looked at, nothing here is
+//! meant to replace a real decoder.
+//!
+
+use crate::common::Codec;
+
+/// Returns `true` if the leading bytes of `data` cannot possibly start a
+/// valid frame for `codec`.
+///
+/// Empty payloads are accepted, since encoders use them for dropped frames.
+pub(crate) fn looks_corrupted(codec: Codec, data: &[u8]) -> bool {
+    if data.is_empty() {
+        return false;
+    }
+
+    match codec {
+        Codec::VP8 => vp8_corrupted(data),
+        Codec::VP9 => vp9_corrupted(data),
+        Codec::AV1 => av1_corrupted(data),
+    }
+}
+
+/// Returns `true` if `data` holds a frame that can be decoded on its own.
+pub(crate) fn is_keyframe(codec: Codec, data: &[u8]) -> bool {
+    match codec {
+        Codec::VP8 => data.len() >= 3 && data[0] & 1 == 0,
+        Codec::VP9 => vp9_is_keyframe(data),
+        Codec::AV1 => av1_is_keyframe(data),
+    }
+}
+
+/// Returns the coded frame size signalled by `data`, as `(width, height)`.
+///
+/// VP8 and VP9 signal it in keyframes and intra-only frames, AV1 in the
+/// maximum frame size of sequence headers.
+pub(crate) fn coded_size(codec: Codec, data: &[u8]) -> Option<(u32, u32)> {
+    match codec {
+        Codec::VP8 => vp8_coded_size(data),
+        Codec::VP9 => vp9_frame_size(data).map(|(coded, _)| coded),
+        Codec::AV1 => obus(data)
+            .find(|obu| obu.obu_type == OBU_SEQUENCE_HEADER)
+            .and_then(|obu| av1_max_frame_size(obu.payload)),
+    }
+}
+
+/// Minimal MSB-first bit reader
+pub(crate) struct BitReader<'a> {
+    data: &'a [u8],
+    pos: usize,
+}
+
+impl<'a> BitReader<'a> {
+    pub(crate) fn new(data: &'a [u8]) -> Self {
+        BitReader { data, pos: 0 }
+    }
+
+    pub(crate) fn read_bit(&mut self) -> Option<u32> {
+        let byte = self.data.get(self.pos / 8)?;
+        let bit = (byte >> (7 - self.pos % 8)) & 1;
+        self.pos += 1;
+        Some(bit as u32)
+    }
+
+    pub(crate) fn read_bits(&mut self, n: usize) -> Option<u32> {
+        (0..n).try_fold(0, |acc, _| Some(acc << 1 | self.read_bit()?))
+    }
+
+    /// Reads an AV1 `uvlc()` value, saturating as the specification does
+    fn read_uvlc(&mut self) -> Option<u32> {
+        let mut leading_zeros = 0;
+        while self.read_bit()? == 0 {
+            leading_zeros += 1;
+        }
+        if leading_zeros >= 32 {
+            return Some(u32::MAX);
+        }
+        Some(self.read_bits(leading_zeros)? + ((1u64 << leading_zeros) - 1) as u32)
+    }
+}
+
+/// VP8 keyframe header, as described in RFC 6386 section 9.1
+fn vp8_coded_size(data: &[u8]) -> Option<(u32, u32)> {
+    if data.len() < 10 || data[0] & 1 != 0 || data[3..6] != [0x9d, 0x01, 0x2a] {
+        return None;
+    }
+    let width = (data[6] as u32 | (data[7] as u32) << 8) & 0x3fff;
+    let height = (data[8] as u32 | (data[9] as u32) << 8) & 0x3fff;
+    Some((width, height))
+}
+
+/// VP9 uncompressed header, up to the frame type
+fn vp9_is_keyframe(data: &[u8]) -> bool {
+    vp9_frame_type(data) == Some(0)
+}
+
+/// Returns the VP9 `frame_type`, `None` for show-existing-frame
+fn vp9_frame_type(data: &[u8]) -> Option<u32> {
+    let mut br = BitReader::new(data);
+    if br.read_bits(2)? != 0b10 {
+        return None;
+    }
+    let profile = br.read_bit()? | br.read_bit()? << 1;
+    if profile == 3 {
+        br.read_bit()?;
+    }
+    let show_existing_frame = br.read_bit()?;
+    if show_existing_frame == 1 {
+        return None;
+    }
+    br.read_bit()
+}
+
+/// Returns the VP9 render size, signalled by keyframes and intra-only
+/// frames, as `(width, height)`.
+///
+/// It only differs from the coded size for anamorphic content.
+pub(crate) fn vp9_render_size(data: &[u8]) -> Option<(u32, u32)> {
+    vp9_frame_size(data).map(|(_, render)| render)
+}
+
+/// Returns the VP9 coded and render sizes of keyframes and intra-only
+/// frames
+fn vp9_frame_size(data: &[u8]) -> Option<((u32, u32), (u32, u32))> {
+    let mut br = BitReader::new(data);
+    if br.read_bits(2)? != 0b10 {
+        return None;
+    }
+    let profile = br.read_bit()? | br.read_bit()? << 1;
+    if profile == 3 {
+        br.read_bit()?;
+    }
+    if br.read_bit()? == 1 {
+        // show_existing_frame
+        return None;
+    }
+    let frame_type = br.read_bit()?;
+    let show_frame = br.read_bit()?;
+    let error_resilient_mode = br.read_bit()?;
+
+    if frame_type == 0 {
+        vp9_sync_code(&mut br)?;
+        vp9_color_config(&mut br, profile)?;
+    } else {
+        let intra_only = if show_frame == 1 { 0 } else { br.read_bit()? };
+        if intra_only == 0 {
+            return None;
+        }
+        if error_resilient_mode == 0 {
+            // reset_frame_context
+            br.read_bits(2)?;
+        }
+        vp9_sync_code(&mut br)?;
+        if profile > 0 {
+            vp9_color_config(&mut br, profile)?;
+        }
+        // refresh_frame_flags
+        br.read_bits(8)?;
+    }
+
+    let coded = (br.read_bits(16)? + 1, br.read_bits(16)? + 1);
+    if br.read_bit()? == 1 {
+        Some((coded, (br.read_bits(16)? + 1, br.read_bits(16)? + 1)))
+    } else {
+        Some((coded, coded))
+    }
+}
+
+fn vp9_sync_code(br: &mut BitReader) -> Option<()> {
+    (br.read_bits(24)? == 0x498342).then_some(())
+}
+
+fn vp9_color_config(br: &mut BitReader, profile: u32) -> Option<()> {
+    if profile >= 2 {
+        // ten_or_twelve_bit
+        br.read_bit()?;
+    }
+    let color_space = br.read_bits(3)?;
+    if color_space != 7 {
+        // color_range
+        br.read_bit()?;
+        if profile == 1 || profile == 3 {
+            // subsampling_x, subsampling_y, reserved_zero
+            br.read_bits(3)?;
+        }
+    } else if profile == 1 || profile == 3 {
+        // reserved_zero
+        br.read_bit()?;
+    }
+    Some(())
+}
+
+const OBU_SEQUENCE_HEADER: u8 = 1;
+const OBU_TEMPORAL_DELIMITER: u8 = 2;
+const OBU_FRAME_HEADER: u8 = 3;
+const OBU_FRAME: u8 = 6;
+
+/// Reads an unsigned LEB128 value, returning it along with its length
+fn leb128(data: &[u8]) -> Option<(u64, usize)> {
+    let mut value = 0u64;
+    for (i, byte) in data.iter().take(8).enumerate() {
+        value |= ((byte & 0x7f) as u64) << (i * 7);
+        if byte & 0x80 == 0 {
+            return Some((value, i + 1));
+        }
+    }
+    None
+}
+
+/// A single OBU within a temporal unit
+pub(crate) struct Obu<'a> {
+    pub(crate) obu_type: u8,
+    /// Offset of the OBU header within the temporal unit
+    pub(crate) offset: usize,
+    /// Length of the OBU, header included
+    pub(crate) len: usize,
+    pub(crate) payload: &'a [u8],
+}
+
+/// Iterates over the OBUs in an AV1 temporal unit
+pub(crate) fn obus(data: &[u8]) -> impl Iterator<Item = Obu<'_>> {
+    let mut offset = 0;
+    std::iter::from_fn(move || {
+        let header = *data.get(offset)?;
+        let obu_type = (header >> 3) & 0xf;
+        let has_extension = (header >> 2) & 1 == 1;
+        let has_size = (header >> 1) & 1 == 1;
+
+        let mut pos = offset + 1 + has_extension as usize;
+        let size = if has_size {
+            let (size, len) = leb128(data.get(pos..)?)?;
+            pos += len;
+            size as usize
+        } else {
+            data.len().checked_sub(pos)?
+        };
+        let payload = data.get(pos..pos.checked_add(size)?)?;
+        let obu = Obu {
+            obu_type,
+            offset,
+            len: pos + size - offset,
+            payload,
+        };
+        offset = pos + size;
+
+        Some(obu)
+    })
+}
+
+/// Returns the first AV1 sequence header OBU of `data`, OBU header
+/// included
+pub(crate) fn av1_sequence_header(data: &[u8]) -> Option<&[u8]> {
+    obus(data)
+        .find(|obu| obu.obu_type == OBU_SEQUENCE_HEADER)
+        .map(|obu| &data[obu.offset..obu.offset + obu.len])
+}
+
+/// Parses an AV1 sequence header up to `max_frame_height_minus_1`
+fn av1_max_frame_size(payload: &[u8]) -> Option<(u32, u32)> {
+    let mut br = BitReader::new(payload);
+    // seq_profile, still_picture
+    br.read_bits(4)?;
+    let reduced_still_picture_header = br.read_bit()?;
+    if reduced_still_picture_header == 1 {
+        // seq_level_idx[0]
+        br.read_bits(5)?;
+    } else {
+        let timing_info_present_flag = br.read_bit()?;
+        let mut decoder_model_info_present_flag = 0;
+        let mut buffer_delay_length = 0;
+        if timing_info_present_flag == 1 {
+            // num_units_in_display_tick, time_scale
+            br.read_bits(32)?;
+            br.read_bits(32)?;
+            let equal_picture_interval = br.read_bit()?;
+            if equal_picture_interval == 1 {
+                br.read_uvlc()?;
+            }
+            decoder_model_info_present_flag = br.read_bit()?;
+            if decoder_model_info_present_flag == 1 {
+                buffer_delay_length = br.read_bits(5)? as usize + 1;
+                // num_units_in_decoding_tick, buffer_removal_time_length_minus_1,
+                // frame_presentation_time_length_minus_1
+                br.read_bits(32)?;
+                br.read_bits(10)?;
+            }
+        }
+        let initial_display_delay_present_flag = br.read_bit()?;
+        let operating_points_cnt = br.read_bits(5)? + 1;
+        for _ in 0..operating_points_cnt {
+            // operating_point_idc
+            br.read_bits(12)?;
+            let seq_level_idx = br.read_bits(5)?;
+            if seq_level_idx > 7 {
+                // seq_tier
+                br.read_bit()?;
+            }
+            if decoder_model_info_present_flag == 1 && br.read_bit()? == 1 {
+                // decoder_buffer_delay, encoder_buffer_delay, low_delay_mode_flag
+                br.read_bits(buffer_delay_length)?;
+                br.read_bits(buffer_delay_length)?;
+                br.read_bit()?;
+            }
+            if initial_display_delay_present_flag == 1 && br.read_bit()? == 1 {
+                // initial_display_delay_minus_1
+                br.read_bits(4)?;
+            }
+        }
+    }
+
+    let frame_width_bits = br.read_bits(4)? as usize + 1;
+    let frame_height_bits = br.read_bits(4)? as usize + 1;
+    let width = br.read_bits(frame_width_bits)? + 1;
+    let height = br.read_bits(frame_height_bits)? + 1;
+    Some((width, height))
+}
+
+/// Returns `true` if `data` only holds a temporal delimiter, which some
+/// encoders write as a frame of its own
+fn av1_temporal_delimiter_only(data: &[u8]) -> bool {
+    // forbidden and reserved bits clear
+    if data.first().map_or(true, |&header| header & 0x81 != 0) {
+        return false;
+    }
+    let mut obus = obus(data);
+    matches!(obus.next(), Some(obu) if obu.obu_type == OBU_TEMPORAL_DELIMITER && obu.len == data.len())
+}
+
+/// AV1 keyframes carry a frame header with `frame_type` set to `KEY_FRAME`
+fn av1_is_keyframe(data: &[u8]) -> bool {
+    if av1_temporal_delimiter_only(data) {
+        return false;
+    }
+    let mut reduced_still_picture_header = false;
+    for obu in obus(data) {
+        let mut br = BitReader::new(obu.payload);
+        match obu.obu_type {
+            OBU_SEQUENCE_HEADER => {
+                // seq_profile, still_picture
+                br.read_bits(4);
+                reduced_still_picture_header = br.read_bit() == Some(1);
+            }
+            OBU_FRAME_HEADER | OBU_FRAME => {
+                if reduced_still_picture_header {
+                    return true;
+                }
+                return br.read_bit() == Some(0) && br.read_bits(2) == Some(0);
+            }
+            _ => {}
+        }
+    }
+    false
+}
+
+/// VP8 frame tag, as described in RFC 6386 section 9.1
+fn vp8_corrupted(data: &[u8]) -> bool {
+    if data.len() < 3 {
+        return true;
+    }
+
+    let tag = data[0] as u32 | (data[1] as u32) << 8 | (data[2] as u32) << 16;
+    let is_key = tag & 1 == 0;
+    let version = (tag >> 1) & 7;
+    let first_part_size = (tag >> 5) as usize;
+
+    if version > 3 || first_part_size > data.len() {
+        return true;
+    }
+
+    is_key && (data.len() < 10 || data[3..6] != [0x9d, 0x01, 0x2a])
+}
+
+/// VP9 uncompressed header frame marker
+fn vp9_corrupted(data: &[u8]) -> bool {
+    data[0] >> 6 != 0b10
+}
+
+/// AV1 OBU header of the first OBU
+fn av1_corrupted(data: &[u8]) -> bool {
+    if av1_temporal_delimiter_only(data) {
+        return false;
+    }
+    let forbidden = data[0] >> 7;
+    let obu_type = (data[0] >> 3) & 0xf;
+    let reserved = data[0] & 1;
+
+    forbidden != 0 || reserved != 0 || !matches!(obu_type, 1..=8 | 15)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn vp8() {
+        let key = [0x50, 0x01, 0x00, 0x9d, 0x01, 0x2a, 0x40, 0x01, 0xf0, 0x00];
+        assert!(!looks_corrupted(Codec::VP8, &key));
+
+        let mut bad_start_code = key;
+        bad_start_code[4] = 0xff;
+        assert!(looks_corrupted(Codec::VP8, &bad_start_code));
+        assert!(looks_corrupted(Codec::VP8, &key[..2]));
+    }
+
+    #[test]
+    fn vp9() {
+        assert!(!looks_corrupted(Codec::VP9, &[0x82, 0x49, 0x83, 0x42]));
+        assert!(looks_corrupted(Codec::VP9, &[0x02, 0x49, 0x83, 0x42]));
+    }
+
+    #[test]
+    fn temporal_delimiter_only() {
+        assert!(av1_temporal_delimiter_only(&[0x12, 0x00]));
+        assert!(!av1_temporal_delimiter_only(&[0x12, 0x00, 0x0a, 0x00]));
+        assert!(!av1_temporal_delimiter_only(&[0x92, 0x00]));
+        assert!(!looks_corrupted(Codec::AV1, &[0x12, 0x00]));
+        assert!(!is_keyframe(Codec::AV1, &[0x12, 0x00]));
+    }
+
+    #[test]
+    fn keyframes() {
+        const IVF: &[u8] = include_bytes!("../assets/single_stream_av1.ivf");
+        let size = u32::from_le_bytes([IVF[32], IVF[33], IVF[34], IVF[35]]) as usize;
+        let first = &IVF[44..44 + size];
+        assert!(is_keyframe(Codec::AV1, first));
+
+        assert!(is_keyframe(Codec::VP8, &[0x50, 0x01, 0x00]));
+        assert!(!is_keyframe(Codec::VP8, &[0x51, 0x01, 0x00]));
+        assert!(is_keyframe(Codec::VP9, &[0x82, 0x49, 0x83, 0x42]));
+        assert!(!is_keyframe(Codec::VP9, &[0x86, 0x00]));
+    }
+
+    #[test]
+    fn vp9_render_size() {
+        use crate::test_util::vp9_keyframe;
+
+        let frame = vp9_keyframe(640, 360, Some((480, 360)));
+        assert_eq!(super::vp9_render_size(&frame), Some((480, 360)));
+        let frame = vp9_keyframe(640, 360, None);
+        assert_eq!(super::vp9_render_size(&frame), Some((640, 360)));
+
+        // inter frames do not signal it
+        assert_eq!(super::vp9_render_size(&[0x86, 0x00]), None);
+        assert_eq!(super::vp9_render_size(&frame[..6]), None);
+    }
+
+    #[test]
+    fn coded_size() {
+        const IVF: &[u8] = include_bytes!("../assets/single_stream_av1.ivf");
+        let header = crate::demuxer::ivf_header(IVF).unwrap().1;
+        let first = &IVF[32 + 12..32 + 12 + 0x952];
+        assert_eq!(
+            super::coded_size(Codec::AV1, first),
+            Some((header.width as u32, header.height as u32))
+        );
+
+        let vp8 = [0x50, 0x01, 0x00, 0x9d, 0x01, 0x2a, 0x40, 0x01, 0xf0, 0x00];
+        assert_eq!(super::coded_size(Codec::VP8, &vp8), Some((320, 240)));
+        assert_eq!(super::coded_size(Codec::VP8, &[0x51, 0x01, 0x00]), None);
+
+        let frame = crate::test_util::vp9_keyframe(640, 360, Some((480, 360)));
+        assert_eq!(super::coded_size(Codec::VP9, &frame), Some((640, 360)));
+    }
+
+    #[test]
+    fn av1() {
+        assert!(!looks_corrupted(Codec::AV1, &[0x12, 0x00]));
+        assert!(looks_corrupted(Codec::AV1, &[0x92, 0x00]));
+        assert!(looks_corrupted(Codec::AV1, &[0x02, 0x00]));
+    }
+}
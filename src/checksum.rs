@@ -0,0 +1,66 @@
+//!
+//! Payload checksum stored in the reserved header field.
+//!
+//! This is an extension specific to this crate: when enabled, the last 4
+//! bytes of the header hold the CRC-32 (IEEE 802.3) of every frame payload
+//! concatenated in stream order. Other tools leave the field at 0, which
+//! disables the verification, so a stream whose checksum happens to be 0
+//! cannot be verified either.
+//!
+
+/// Lookup table for the reflected 0xEDB88320 polynomial
+const TABLE: [u32; 256] = {
+    let mut table = [0u32; 256];
+    let mut i = 0;
+    while i < 256 {
+        let mut crc = i as u32;
+        let mut bit = 0;
+        while bit < 8 {
+            crc = if crc & 1 == 1 {
+                0xedb8_8320 ^ (crc >> 1)
+            } else {
+                crc >> 1
+            };
+            bit += 1;
+        }
+        table[i] = crc;
+        i += 1;
+    }
+    table
+};
+
+/// Running CRC-32 over the frame payloads.
+#[derive(Clone, Copy, Debug)]
+pub(crate) struct Crc32(u32);
+
+impl Default for Crc32 {
+    fn default() -> Crc32 {
+        Crc32(!0)
+    }
+}
+
+impl Crc32 {
+    pub(crate) fn update(&mut self, data: &[u8]) {
+        for &byte in data {
+            self.0 = TABLE[((self.0 ^ byte as u32) & 0xff) as usize] ^ (self.0 >> 8);
+        }
+    }
+
+    pub(crate) fn value(&self) -> u32 {
+        !self.0
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn check_value() {
+        let mut crc = Crc32::default();
+        crc.update(b"12345");
+        crc.update(b"6789");
+        assert_eq!(crc.value(), 0xcbf4_3926);
+        assert_eq!(Crc32::default().value(), 0);
+    }
+}
@@ -1,15 +1,25 @@
+use std::cmp::Ordering;
+
 pub use av_format::common::GlobalInfo;
 
-#[derive(Copy, Clone, Debug)]
-pub(crate) enum Codec {
+use av_data::rational::Rational64;
+
+#[derive(Copy, Clone, Debug, Default, PartialEq, Eq)]
+pub enum Codec {
+    #[default]
     VP8,
     VP9,
     AV1,
 }
 
-impl Default for Codec {
-    fn default() -> Codec {
-        Codec::VP8
+impl Codec {
+    /// Returns the FourCC identifying the codec in the IVF header.
+    pub fn fourcc(self) -> &'static [u8; 4] {
+        match self {
+            Codec::VP8 => b"VP80",
+            Codec::VP9 => b"VP90",
+            Codec::AV1 => b"AV01",
+        }
     }
 }
 
@@ -22,3 +32,179 @@ impl From<Codec> for String {
         }
     }
 }
+
+/// Width of the frame count stored in the header.
+///
+/// Some descriptions of the format treat the reserved field at offset 28
+/// as the upper half of a 64-bit frame count starting at offset 24.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum FrameCountWidth {
+    /// 32-bit frame count followed by a reserved field, as libvpx writes
+    #[default]
+    Bits32,
+    /// 64-bit frame count spanning the reserved field
+    Bits64,
+}
+
+/// Size of the IVF file header, in bytes.
+pub const IVF_HEADER_LEN: usize = 32;
+
+/// Offset of the reserved field within the IVF file header.
+pub(crate) const IVF_RESERVED_OFFSET: usize = 28;
+
+/// Size of the header preceding each frame payload, in bytes.
+pub const IVF_FRAME_HEADER_LEN: usize = 12;
+
+/// Timestamp bit used by some tools to flag keyframes.
+pub(crate) const TIMESTAMP_KEYFRAME_FLAG: u64 = 1 << 63;
+
+/// Converts a frame timestamp expressed in `timebase` units to seconds.
+///
+/// The intermediate product is computed on 128 bit integers, so even
+/// timestamps close to `u64::MAX` paired with a fine grained timebase
+/// (e.g. microseconds) neither panic nor wrap around.
+///
+/// Returns `None` if the timebase has a zero denominator.
+pub fn timestamp_to_seconds(ts: u64, timebase: Rational64) -> Option<f64> {
+    let numer = *timebase.numer() as i128;
+    let denom = *timebase.denom() as i128;
+    if denom == 0 {
+        return None;
+    }
+
+    let ticks = (ts as i128).checked_mul(numer)?;
+    let whole = ticks / denom;
+    let rem = ticks % denom;
+
+    Some(whole as f64 + rem as f64 / denom as f64)
+}
+
+/// A timestamp together with the timebase it is expressed in.
+///
+/// Timestamps are ordered and compared by the instant they represent, so
+/// the same instant in two timebases is equal. The timebase must have a
+/// positive denominator, comparing timestamps with a zero denominator
+/// panics.
+#[derive(Copy, Clone, Debug)]
+pub struct Timestamp {
+    /// Number of `timebase` units
+    pub value: u64,
+    /// Duration of a unit in seconds
+    pub timebase: Rational64,
+}
+
+impl Timestamp {
+    pub fn new(value: u64, timebase: Rational64) -> Timestamp {
+        Timestamp { value, timebase }
+    }
+
+    /// Returns the timestamp in seconds, see `timestamp_to_seconds`.
+    pub fn to_seconds(&self) -> Option<f64> {
+        timestamp_to_seconds(self.value, self.timebase)
+    }
+
+    /// Expresses the timestamp in `timebase`, rounding to the nearest unit.
+    ///
+    /// Returns `None` if either timebase is degenerate or the result does
+    /// not fit in 64 bits.
+    pub fn rescale(&self, timebase: Rational64) -> Option<Timestamp> {
+        let numer = *self.timebase.numer() as i128 * *timebase.denom() as i128;
+        let denom = *self.timebase.denom() as i128 * *timebase.numer() as i128;
+        if denom <= 0 || numer < 0 {
+            return None;
+        }
+
+        let ticks = (self.value as i128).checked_mul(numer)?;
+        let value = (ticks + denom / 2) / denom;
+        Some(Timestamp {
+            value: u64::try_from(value).ok()?,
+            timebase,
+        })
+    }
+
+    /// Splits the instant in whole seconds and a remainder, over the
+    /// timebase denominator.
+    fn split(&self) -> (i128, i128, i128) {
+        let ticks = self.value as i128 * *self.timebase.numer() as i128;
+        let denom = *self.timebase.denom() as i128;
+        (ticks.div_euclid(denom), ticks.rem_euclid(denom), denom)
+    }
+}
+
+impl Ord for Timestamp {
+    fn cmp(&self, other: &Timestamp) -> Ordering {
+        let (whole, rem, denom) = self.split();
+        let (other_whole, other_rem, other_denom) = other.split();
+
+        // remainders are below their denominator, the products fit
+        whole
+            .cmp(&other_whole)
+            .then_with(|| (rem * other_denom).cmp(&(other_rem * denom)))
+    }
+}
+
+impl PartialOrd for Timestamp {
+    fn partial_cmp(&self, other: &Timestamp) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl PartialEq for Timestamp {
+    fn eq(&self, other: &Timestamp) -> bool {
+        self.cmp(other) == Ordering::Equal
+    }
+}
+
+impl Eq for Timestamp {}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn timestamp_to_seconds_large() {
+        let timebase = Rational64::new(1, 1_000_000);
+        let ts = u64::MAX - 1;
+
+        let secs = timestamp_to_seconds(ts, timebase).unwrap();
+
+        assert!(secs.is_finite());
+        assert!((secs - ts as f64 / 1_000_000.0).abs() < 1.0);
+    }
+
+    #[test]
+    fn timestamp_to_seconds_exact() {
+        let timebase = Rational64::new(1001, 30000);
+
+        assert_eq!(timestamp_to_seconds(30000, timebase), Some(1001.0));
+        assert_eq!(timestamp_to_seconds(0, timebase), Some(0.0));
+    }
+
+    #[test]
+    fn timestamp_rescale() {
+        let ms = Rational64::new(1, 1000);
+        let ntsc = Rational64::new(1001, 30000);
+
+        let ts = Timestamp::new(1001, ms);
+        let rescaled = ts.rescale(ntsc).unwrap();
+        assert_eq!(rescaled.value, 30);
+        assert_eq!(rescaled, ts);
+        assert_eq!(rescaled.rescale(ms).unwrap().value, 1001);
+        assert_eq!(rescaled.to_seconds(), Some(1.001));
+
+        // 66.73ms
+        assert_eq!(Timestamp::new(2, ntsc).rescale(ms).unwrap().value, 67);
+        assert_eq!(Timestamp::new(1, ms).rescale(Rational64::new(0, 1)), None);
+    }
+
+    #[test]
+    fn timestamp_ord() {
+        let ms = Rational64::new(1, 1000);
+        let ntsc = Rational64::new(1001, 30000);
+
+        assert!(Timestamp::new(33, ms) < Timestamp::new(1, ntsc));
+        assert!(Timestamp::new(34, ms) > Timestamp::new(1, ntsc));
+        assert_eq!(Timestamp::new(0, ms), Timestamp::new(0, ntsc));
+        assert!(Timestamp::new(u64::MAX, ms) > Timestamp::new(u64::MAX - 1, ms));
+    }
+}
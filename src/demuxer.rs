@@ -4,11 +4,26 @@
 //!
 //! Internally the parsing is implement with the `nom` parser
 //!
+//! `Packet::data` is an owned `Vec<u8>`, so every payload is copied once
+//! out of the read buffer and then moved into its packet without further
+//! copies. See the `pool` module to reuse those buffers. Use
+//! `IvfFrameReader` or `scan` to inspect payloads without going through
+//! packets.
+//!
+//! Packets sharing a reference-counted buffer, such as `Bytes` or
+//! `Arc<[u8]>`, are not supported: that needs av-data to change the type
+//! of `Packet::data` first, so no feature flag enables them.
+//!
 
-use std::collections::VecDeque;
-use std::io::SeekFrom;
+use std::any::Any;
+use std::collections::{HashMap, VecDeque};
+use std::fs::File;
+use std::io::{self, Read, SeekFrom};
+use std::ops::ControlFlow;
+use std::path::Path;
+use std::sync::Arc;
 
-use log::{debug, error};
+use log::{debug, error, warn};
 
 use nom::bytes::streaming::tag;
 use nom::bytes::streaming::take;
@@ -18,117 +33,1077 @@ use nom::sequence::tuple;
 use nom::{Err, IResult, Needed, Offset};
 
 use av_bitstream::byteread::*;
+use av_bitstream::bytewrite::*;
 use av_data::packet::Packet;
 use av_data::params::{CodecParams, MediaKind, VideoInfo};
 use av_data::rational::Rational64;
 use av_data::timeinfo::TimeInfo;
 pub use av_format::buffer::{AccReader, Buffered};
 use av_format::common::GlobalInfo;
-use av_format::demuxer::Event;
+pub use av_format::demuxer::Event;
 pub use av_format::demuxer::{Context, Demuxer};
 use av_format::demuxer::{Descr, Descriptor};
 use av_format::error::*;
 use av_format::stream::Stream;
 
-use crate::common::Codec;
+use crate::bitstream::{
+    av1_sequence_header, coded_size, is_keyframe, looks_corrupted, vp9_render_size,
+};
+use crate::checksum::Crc32;
+use crate::common::{
+    Codec, FrameCountWidth, Timestamp, IVF_FRAME_HEADER_LEN, IVF_HEADER_LEN,
+    TIMESTAMP_KEYFRAME_FLAG,
+};
+use crate::error::IvfError;
+use crate::index::{is_trailer, read_index, trailer_len, IndexEntry};
+use crate::pool::BufferPool;
+use crate::reader::IvfFrameReader;
+use crate::sidecar::{read_sidecar, FrameMetadata};
 
 #[derive(Default)]
 pub struct IvfDemuxer {
     header: Option<IvfHeader>,
+    /// Events due after the one returned, such as the packet following a
+    /// `NewStream`, see `set_max_queued_events`
     queue: VecDeque<Event>,
+    max_queued_events: Option<usize>,
+    keyframe_in_timestamp_msb: bool,
+    detect_corruption: bool,
+    compute_durations: bool,
+    pending: Option<Packet>,
+    frames_emitted: u32,
+    start: Option<u64>,
+    max_frames: Option<u32>,
+    frame_limit: Option<usize>,
+    timestamp_is_byte_offset: bool,
+    pool: Option<BufferPool>,
+    raw_header: Vec<u8>,
+    /// Bytes buffered when the header was last found incomplete
+    header_buffered: Option<usize>,
+    forced_codec: Option<Codec>,
+    render_size: Option<(u32, u32)>,
+    lenient: bool,
+    keyframe_policy: KeyframePolicy,
+    frames_parsed: u32,
+    index: Option<Vec<IndexEntry>>,
+    use_index: bool,
+    seek_timestamp: Option<u64>,
+    big_endian: bool,
+    frame_count_width: FrameCountWidth,
+    narrow_timestamps: bool,
+    wrap_offset: u64,
+    last_narrow_timestamp: Option<u64>,
+    codec_config: Option<Vec<u8>>,
+    clamp_factor: Option<f64>,
+    recent_deltas: VecDeque<u64>,
+    last_timestamp: Option<u64>,
+    enforce_frame_count: bool,
+    sidecar: Option<HashMap<u32, Vec<u8>>>,
+    checksum: Option<Crc32>,
+    partial_frames: bool,
+    partial: Option<PartialState>,
+    stream_updates: bool,
+    signed_timestamps: bool,
+}
+
+/// Frame being emitted in pieces
+struct PartialState {
+    token: PartialFrame,
+    /// The first packet of the frame, without its data
+    template: Packet,
+}
+
+/// Locates a packet holding part of a frame, see
+/// `IvfDemuxer::set_partial_frames`.
+///
+/// Stored in the `TimeInfo::user_private` of the packet.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct PartialFrame {
+    /// Index of the frame in the stream
+    pub frame_index: u32,
+    /// Offset of the packet data within the frame payload
+    pub offset: u32,
+    /// Size of the whole frame payload
+    pub size: u32,
+}
+
+impl PartialFrame {
+    /// Returns the location of the data of `pkt`, `None` if it holds a
+    /// whole frame.
+    pub fn of(pkt: &Packet) -> Option<&PartialFrame> {
+        pkt.t.user_private.as_ref()?.downcast_ref()
+    }
+
+    /// Returns `true` if `pkt` completes the frame.
+    pub fn is_last(&self, pkt: &Packet) -> bool {
+        self.offset as usize + pkt.data.len() == self.size as usize
+    }
+}
+
+/// Most significant bit of a frame timestamp read as signed
+const SIGN_BIT: u64 = 1 << 63;
+
+/// Events queued at most unless `set_max_queued_events` says otherwise
+const DEFAULT_MAX_QUEUED_EVENTS: usize = 64;
+
+/// Number of timestamp deltas the median is taken over when clamping
+const CLAMP_WINDOW: usize = 15;
+
+/// How the demuxer sets `Packet::is_key`.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum KeyframePolicy {
+    /// Never mark packets as keyframes
+    #[default]
+    AllFalse,
+    /// Mark only the first frame, enough to seek back to the start
+    FirstOnly,
+    /// Parse the frame headers of the codec
+    Detect,
 }
 
 #[derive(Clone, Debug)]
 pub struct IvfHeader {
-    #[allow(dead_code)]
-    version: u16,
-    width: u16,
-    height: u16,
-    rate: u32,
-    #[allow(dead_code)]
-    scale: u32,
-    codec: Codec,
-    nframe: u32,
+    /// Format version, should be 0
+    pub version: u16,
+    /// Header length in bytes, should be 32
+    pub header_len: u16,
+    /// Frame width in pixels
+    pub width: u16,
+    /// Frame height in pixels
+    pub height: u16,
+    /// Timebase denominator
+    pub rate: u32,
+    /// Timebase numerator
+    pub scale: u32,
+    /// Codec as signalled by the FourCC
+    pub codec: Codec,
+    /// Number of frames declared in the header
+    pub nframe: u32,
+    /// Trailing reserved field, should be 0
+    pub reserved: u32,
 }
 
 #[derive(Debug, PartialEq, Eq)]
 pub struct IvfFrame {
-    size: u32,
-    pos: u64,
-    data: Vec<u8>,
+    /// Payload size in bytes
+    pub size: u32,
+    /// Presentation timestamp in timebase units
+    pub timestamp: u64,
+    /// Frame payload
+    pub data: Vec<u8>,
+}
+
+impl IvfHeader {
+    /// Returns the timebase of the frame timestamps.
+    pub fn timebase(&self) -> Rational64 {
+        Rational64::new_raw(self.scale as i64, self.rate as i64)
+    }
+
+    /// Returns a frame timestamp paired with the header timebase.
+    pub fn timestamp(&self, value: u64) -> Timestamp {
+        Timestamp::new(value, self.timebase())
+    }
+
+    /// Returns the declared frame count, spanning the reserved field if
+    /// `width` is `FrameCountWidth::Bits64`.
+    pub fn frame_count(&self, width: FrameCountWidth) -> u64 {
+        match width {
+            FrameCountWidth::Bits32 => self.nframe as u64,
+            FrameCountWidth::Bits64 => (self.reserved as u64) << 32 | self.nframe as u64,
+        }
+    }
+
+    /// Returns `true` if the header was most likely written big-endian.
+    ///
+    /// The header length field, always 32, reads 8192 in that case.
+    pub fn looks_byte_swapped(&self) -> bool {
+        self.header_len != IVF_HEADER_LEN as u16
+            && self.header_len.swap_bytes() == IVF_HEADER_LEN as u16
+    }
+
+    /// Compares every field, including the ones that do not affect
+    /// decoding, such as the header length and the reserved bytes.
+    pub fn eq_exact(&self, other: &IvfHeader) -> bool {
+        self.version == other.version
+            && self.header_len == other.header_len
+            && self.width == other.width
+            && self.height == other.height
+            && self.rate == other.rate
+            && self.scale == other.scale
+            && self.codec == other.codec
+            && self.nframe == other.nframe
+            && self.reserved == other.reserved
+    }
+
+    /// Serializes the header as the 32 bytes found at the start of a file.
+    pub fn to_bytes(&self) -> [u8; IVF_HEADER_LEN] {
+        let mut buf = [0u8; IVF_HEADER_LEN];
+        buf[0..4].copy_from_slice(b"DKIF");
+        put_u16l(&mut buf[4..6], self.version);
+        put_u16l(&mut buf[6..8], self.header_len);
+        buf[8..12].copy_from_slice(self.codec.fourcc());
+        put_u16l(&mut buf[12..14], self.width);
+        put_u16l(&mut buf[14..16], self.height);
+        put_u32l(&mut buf[16..20], self.rate);
+        put_u32l(&mut buf[20..24], self.scale);
+        put_u32l(&mut buf[24..28], self.nframe);
+        put_u32l(&mut buf[28..32], self.reserved);
+        buf
+    }
+
+    /// Builds the `GlobalInfo` describing the stream, as `read_headers` does
+    /// with the default options.
+    ///
+    /// The codec configuration and the start time are not known from the
+    /// header alone, they are left unset.
+    pub fn to_global_info(&self) -> GlobalInfo {
+        let mut info = GlobalInfo {
+            duration: None,
+            timebase: Some(self.timebase()),
+            streams: Vec::new(),
+        };
+        info.add_stream(self.to_stream());
+        info
+    }
+
+    fn to_stream(&self) -> Stream {
+        Stream {
+            id: 0,
+            index: 0,
+            params: CodecParams {
+                extradata: None,
+                bit_rate: self.rate as usize,
+                delay: 0,
+                convergence_window: 0,
+                codec_id: Some(self.codec.into()),
+                kind: Some(MediaKind::Video(VideoInfo {
+                    width: self.width as usize,
+                    height: self.height as usize,
+                    format: None,
+                })),
+            },
+            start: None,
+            duration: Some(self.nframe as u64),
+            timebase: Rational64::new(1, 1000 * 1000 * 1000),
+            // lets IvfMuxer reproduce the fields not mapped above
+            user_private: Some(Arc::new(self.clone())),
+        }
+    }
+
+    /// Returns an annotated hexdump of `to_bytes`, see `header_hexdump`.
+    pub fn hexdump(&self) -> String {
+        header_hexdump(&self.to_bytes())
+    }
+}
+
+/// Header fields as `(label, offset, length)`
+const HEADER_FIELDS: &[(&str, usize, usize)] = &[
+    ("magic", 0, 4),
+    ("version", 4, 2),
+    ("length", 6, 2),
+    ("fourcc", 8, 4),
+    ("width", 12, 2),
+    ("height", 14, 2),
+    ("rate", 16, 4),
+    ("scale", 20, 4),
+    ("count", 24, 4),
+    ("reserved", 28, 4),
+];
+
+/// Dumps raw header bytes one field per line, with its offset, bytes,
+/// label and little-endian value, e.g. to paste in a bug report.
+///
+/// Feed it `IvfDemuxer::raw_header_bytes` to see what the file holds
+/// rather than what was parsed from it. Fields past the end of `raw` are
+/// reported as missing.
+pub fn header_hexdump(raw: &[u8]) -> String {
+    let mut out = String::new();
+    for &(label, offset, len) in HEADER_FIELDS {
+        let bytes = match raw.get(offset..offset + len) {
+            Some(bytes) => bytes,
+            None => {
+                out.push_str(&format!(
+                    "{:#04x}  {:<12} {:<9} missing\n",
+                    offset, "", label
+                ));
+                continue;
+            }
+        };
+        let hex = bytes
+            .iter()
+            .map(|b| format!("{:02x}", b))
+            .collect::<Vec<_>>()
+            .join(" ");
+        let value = match label {
+            "magic" | "fourcc" => format!("{:?}", String::from_utf8_lossy(bytes)),
+            _ => bytes
+                .iter()
+                .rev()
+                .fold(0u64, |acc, &b| acc << 8 | b as u64)
+                .to_string(),
+        };
+        out.push_str(&format!(
+            "{:#04x}  {:<12} {:<9} {}\n",
+            offset, hex, label, value
+        ));
+    }
+    out
 }
 
 impl IvfDemuxer {
     pub fn new() -> IvfDemuxer {
         Default::default()
     }
-}
 
-impl Demuxer for IvfDemuxer {
-    fn read_headers(&mut self, buf: &mut dyn Buffered, info: &mut GlobalInfo) -> Result<SeekFrom> {
-        match ivf_header(buf.data()) {
-            Ok((input, header)) => {
-                debug!("found header: {:?}", header);
-                let st = Stream {
-                    id: 0,
-                    index: 0,
-                    params: CodecParams {
-                        extradata: None,
-                        bit_rate: header.rate as usize,
-                        delay: 0,
-                        convergence_window: 0,
-                        codec_id: Some(header.codec.into()),
-                        kind: Some(MediaKind::Video(VideoInfo {
-                            width: header.width as usize,
-                            height: header.height as usize,
-                            format: None,
-                        })),
-                    },
-                    start: None,
-                    duration: Some(header.nframe as u64),
-                    timebase: Rational64::new(1, 1000 * 1000 * 1000),
-                    user_private: None,
-                };
-                self.header = Some(header);
-                info.add_stream(st);
-                Ok(SeekFrom::Current(buf.data().offset(input) as i64))
+    /// Reads a whole IVF stream from `r`, returning its header and the
+    /// packets a default `IvfDemuxer` would emit.
+    ///
+    /// This needs neither `Context` nor the format descriptor, nor a
+    /// seekable source.
+    pub fn demux_all<R: Read>(r: R) -> Result<(IvfHeader, Vec<Packet>)> {
+        let reader = IvfFrameReader::new(r)?;
+        let mut demuxer = IvfDemuxer {
+            header: Some(reader.header().clone()),
+            ..IvfDemuxer::new()
+        };
+
+        let mut packets = Vec::new();
+        for (index, frame) in reader.enumerate() {
+            packets.push(demuxer.packet_from_frame(frame?, index as u32));
+        }
+
+        Ok((demuxer.header.unwrap(), packets))
+    }
+
+    /// Run a cheap sanity check on the leading bytes of each payload and
+    /// mark the packets that cannot be valid for the codec as corrupted.
+    ///
+    /// Corrupted packets are still emitted, so players may skip them.
+    pub fn set_detect_corruption(&mut self, enable: bool) {
+        self.detect_corruption = enable;
+    }
+
+    /// Read the keyframe flag from the most significant bit of the frame
+    /// timestamp, stripping it from the timestamp itself.
+    ///
+    /// This matches `IvfMuxer::set_encode_keyframe_in_timestamp_msb` and is
+    /// off by default, since it is not part of the IVF specification.
+    pub fn set_decode_keyframe_in_timestamp_msb(&mut self, enable: bool) {
+        self.keyframe_in_timestamp_msb = enable;
+    }
+
+    /// Only use the low 32 bits of the frame timestamps, as written by
+    /// tools storing them on 32 bits, and keep counting when they wrap
+    /// around.
+    ///
+    /// A timestamp lower than the previous one by more than 2^31 is taken
+    /// as a wraparound, so packet timestamps and durations keep increasing.
+    pub fn set_narrow_timestamps(&mut self, enable: bool) {
+        self.narrow_timestamps = enable;
+    }
+
+    /// Replace the timestamps that follow the previous one by more than
+    /// `factor` times the median delta, or precede it, and flag those
+    /// packets as corrupted. Off (`None`) by default.
+    ///
+    /// The median is taken over the last few frames, and a replaced
+    /// timestamp is the previous one plus that median, which is where the
+    /// frame falls for constant frame rate content.
+    pub fn set_timestamp_clamping(&mut self, factor: Option<f64>) {
+        self.clamp_factor = factor;
+    }
+
+    /// Compute each packet duration from the timestamp of the following
+    /// frame.
+    ///
+    /// Packets are held back by one frame, the last one gets no duration.
+    pub fn set_compute_durations(&mut self, enable: bool) {
+        self.compute_durations = enable;
+    }
+
+    /// Ignore the header FourCC, which may be unknown or wrong, and handle
+    /// the stream as `codec`.
+    ///
+    /// Must be called before `read_headers`.
+    pub fn force_codec(&mut self, codec: Codec) {
+        self.forced_codec = Some(codec);
+    }
+
+    /// Choose how keyframes are flagged, `KeyframePolicy::AllFalse` by
+    /// default.
+    ///
+    /// The policy is ignored when keyframes are decoded from the timestamp
+    /// MSB, see `set_decode_keyframe_in_timestamp_msb`.
+    pub fn set_keyframe_policy(&mut self, policy: KeyframePolicy) {
+        self.keyframe_policy = policy;
+    }
+
+    /// Flag keyframes according to an index trailer, read beforehand with
+    /// `index::read_index`, instead of looking at the payloads.
+    ///
+    /// The entries are matched to frames in order. This overrides the
+    /// keyframe policy, which still applies to frames past the last entry.
+    ///
+    /// With a source that can seek, `set_use_index` lets `read_headers`
+    /// find the trailer by itself.
+    pub fn set_index(&mut self, index: Vec<IndexEntry>) {
+        self.index = Some(index);
+    }
+
+    /// Look for the index trailer at the end of the source in
+    /// `read_headers`, and use it as `set_index` would.
+    ///
+    /// This costs a seek to the end and back, so it is off by default. It
+    /// is implied by `set_seek_timestamp`.
+    pub fn set_use_index(&mut self, use_index: bool) {
+        self.use_index = use_index;
+    }
+
+    /// Start demuxing at the last keyframe with a timestamp not greater
+    /// than `timestamp`, located through the index trailer.
+    ///
+    /// Without an index trailer, or if no such keyframe exists, demuxing
+    /// starts at the first frame. The checksum, see `set_verify_checksum`,
+    /// cannot be verified past such a seek.
+    pub fn set_seek_timestamp(&mut self, timestamp: u64) {
+        self.seek_timestamp = Some(timestamp);
+    }
+
+    /// Accept the 28 bytes headers, lacking the reserved field, written by
+    /// some legacy tools.
+    ///
+    /// Such headers are rejected by default.
+    pub fn set_lenient(&mut self, lenient: bool) {
+        self.lenient = lenient;
+    }
+
+    /// Parse the header fields as big-endian, as written by some broken
+    /// muxers, instead of little-endian as the specification mandates.
+    ///
+    /// Only the file header is affected, see
+    /// `IvfHeader::looks_byte_swapped` to detect such files.
+    pub fn set_assume_big_endian(&mut self, big_endian: bool) {
+        self.big_endian = big_endian;
+    }
+
+    /// Choose how the frame count is read, `FrameCountWidth::Bits32` by
+    /// default.
+    ///
+    /// With `FrameCountWidth::Bits64` the reserved field holds the upper
+    /// half of the stream duration.
+    pub fn set_frame_count_width(&mut self, width: FrameCountWidth) {
+        self.frame_count_width = width;
+    }
+
+    /// Returns the decoder initialization data found so far: the first AV1
+    /// sequence header OBU.
+    ///
+    /// VP8 and VP9 carry their configuration in every keyframe, so `None`
+    /// is returned for them.
+    pub fn codec_config(&self) -> Option<Vec<u8>> {
+        self.codec_config.clone()
+    }
+
+    /// Returns the render size signalled by the last VP9 keyframe or
+    /// intra-only frame, as `(width, height)`.
+    ///
+    /// It differs from the coded size in the header for anamorphic content.
+    pub fn render_size(&self) -> Option<(u32, u32)> {
+        self.render_size
+    }
+
+    /// Returns the bytes parsed as the header, once `read_headers` is done.
+    pub fn raw_header_bytes(&self) -> Option<&[u8]> {
+        self.header.as_ref().map(|_| self.raw_header.as_slice())
+    }
+
+    /// Check the payloads against the CRC-32 stored in the reserved header
+    /// field by `IvfMuxer::set_write_checksum`, failing with
+    /// `IvfError::ChecksumMismatch` on mismatch.
+    ///
+    /// The check runs along with the last frame, whatever the frame count
+    /// declared in the header, or on reaching an index trailer: the
+    /// demuxer reads ahead once a frame exhausts the buffer, since
+    /// `av-format` reports the end of the data without involving it.
+    ///
+    /// This is an extension specific to this crate. Streams with a zero
+    /// reserved field, as written by other tools, are not checked.
+    pub fn set_verify_checksum(&mut self, enable: bool) {
+        self.checksum = enable.then(Crc32::default);
+    }
+
+    /// Attach the per-frame metadata of the sidecar file at `path` to the
+    /// packets, as a `FrameMetadata` in `Packet::t.user_private`.
+    ///
+    /// The whole sidecar is read right away, see the `sidecar` module for
+    /// its layout.
+    pub fn set_sidecar<P: AsRef<Path>>(&mut self, path: P) -> io::Result<()> {
+        self.sidecar = Some(read_sidecar(&mut File::open(path)?)?);
+        Ok(())
+    }
+
+    /// Hold the stream to the frame count declared in the header: fail with
+    /// `IvfError::TooManyFrames` instead of emitting packets past it.
+    ///
+    /// Streams declaring no frames are not checked, since some muxers never
+    /// fill the count in.
+    pub fn set_enforce_frame_count(&mut self, enforce: bool) {
+        self.enforce_frame_count = enforce;
+    }
+
+    /// Fail with `IvfError::TooManyFrames` instead of emitting more than
+    /// `max_frames` packets.
+    ///
+    /// Unlimited by default, set a limit when parsing untrusted input.
+    pub fn set_max_frames(&mut self, max_frames: Option<u32>) {
+        self.max_frames = max_frames;
+    }
+
+    /// Ignore the timestamp field and number the frames instead, one tick
+    /// of the header timebase apart, starting from 0.
+    ///
+    /// Older versions of `IvfMuxer` stored the byte position of the packet
+    /// in that field, this recovers usable timestamps from such files.
+    pub fn set_timestamp_is_byte_offset(&mut self, enable: bool) {
+        self.timestamp_is_byte_offset = enable;
+    }
+
+    /// Copy the payloads into buffers taken from `pool`, which the consumer
+    /// fills back with `BufferPool::recycle`. Off (`None`) by default.
+    pub fn set_buffer_pool(&mut self, pool: Option<BufferPool>) {
+        self.pool = pool;
+    }
+
+    /// Emit the frames that are not entirely buffered yet in pieces, so
+    /// decoding can start before the whole payload arrived. Off by default.
+    ///
+    /// Each piece is a packet carrying the bytes available so far and a
+    /// `PartialFrame` in its `TimeInfo::user_private`, locating them
+    /// within the frame: the consumer appends the pieces of a frame until
+    /// `PartialFrame::is_last`. The pieces share the timing and keyframe
+    /// flag of the frame, detected on the first one. Packets without a
+    /// `PartialFrame` hold whole frames.
+    ///
+    /// Only use it for codecs that tolerate partial input. The sidecar
+    /// metadata and the durations are not set on split frames.
+    pub fn set_partial_frames(&mut self, enable: bool) {
+        self.partial_frames = enable;
+    }
+
+    /// Emit `Event::NewStream` again, right before the packet of the frame,
+    /// whenever an AV1 sequence header differs from the previous one, e.g.
+    /// on a resolution switch. Off by default.
+    ///
+    /// The stream carries the new sequence header as extradata and the
+    /// maximum frame size it signals as dimensions.
+    pub fn set_stream_updates(&mut self, enable: bool) {
+        self.stream_updates = enable;
+    }
+
+    /// Queue at most `max` events behind the one returned by `read_event`,
+    /// 64 by default.
+    ///
+    /// Once the queue is full, the intermediate events, such as the
+    /// `NewStream` of `set_stream_updates`, are dropped and the packet they
+    /// precede is returned right away. Packets are never dropped, so this
+    /// is a soft limit: a packet due after a held one, see
+    /// `set_compute_durations`, is queued regardless.
+    pub fn set_max_queued_events(&mut self, max: usize) {
+        self.max_queued_events = Some(max);
+    }
+
+    /// Whether intermediate events must be dropped rather than queued
+    fn queue_full(&self) -> bool {
+        let max = self.max_queued_events.unwrap_or(DEFAULT_MAX_QUEUED_EVENTS);
+        self.queue.len() >= max
+    }
+
+    /// Read the timestamp field as a signed, two's complement, value, as
+    /// some tools write negative decoding timestamps. Off by default, the
+    /// specification makes it unsigned.
+    ///
+    /// The value is set as both `pts` and `dts` of the packets, and a
+    /// negative start time is left unset in the stream.
+    pub fn set_signed_timestamps(&mut self, enable: bool) {
+        self.signed_timestamps = enable;
+    }
+
+    /// Report `Event::Eof` once `limit` packets have been emitted, whatever
+    /// data follows, e.g. to preview the start of a large file.
+    ///
+    /// Unlike `set_max_frames` this is not an error. Unlimited by default.
+    pub fn set_frame_limit(&mut self, limit: Option<usize>) {
+        self.frame_limit = limit;
+    }
+
+    /// Returns the fraction of the declared frames emitted so far.
+    ///
+    /// Returns `None` if the header does not declare a frame count.
+    pub fn progress(&self) -> Option<f32> {
+        let nframe = self.header.as_ref()?.frame_count(self.frame_count_width);
+        if nframe == 0 {
+            return None;
+        }
+        Some((self.frames_emitted as f32 / nframe as f32).min(1.0))
+    }
+
+    /// Returns the timestamp of the first frame, in the header timebase.
+    ///
+    /// It is known after `read_headers` if the first frame was already
+    /// buffered, otherwise once the first packet is read.
+    pub fn start_timestamp(&self) -> Option<u64> {
+        self.start
+    }
+
+    fn frame_timestamp(&self, frame: &IvfFrame) -> (u64, bool) {
+        if self.timestamp_is_byte_offset {
+            return (self.frames_parsed as u64, false);
+        }
+        let (timestamp, is_key) = if self.keyframe_in_timestamp_msb {
+            let is_key = frame.timestamp & TIMESTAMP_KEYFRAME_FLAG != 0;
+            (frame.timestamp & !TIMESTAMP_KEYFRAME_FLAG, is_key)
+        } else {
+            (frame.timestamp, false)
+        };
+        if self.narrow_timestamps {
+            (timestamp & u32::MAX as u64, is_key)
+        } else {
+            (timestamp, is_key)
+        }
+    }
+
+    /// Keeps the first AV1 sequence header, see `codec_config`
+    fn capture_codec_config(&mut self, frame: &IvfFrame) {
+        if self.codec_config.is_none() && self.header.as_ref().map(|h| h.codec) == Some(Codec::AV1)
+        {
+            self.codec_config = av1_sequence_header(&frame.data).map(<[u8]>::to_vec);
+        }
+    }
+
+    /// Compares the payloads read so far to the checksum in the header,
+    /// once
+    fn verify_checksum(&mut self) -> Result<()> {
+        let checksum = match self.checksum.take() {
+            Some(checksum) => checksum,
+            None => return Ok(()),
+        };
+        let expected = self.header.as_ref().map_or(0, |header| header.reserved);
+        let actual = checksum.value();
+        if expected != 0 && expected != actual {
+            error!("payload checksum {:#x}, expected {:#x}", actual, expected);
+            let e = IvfError::ChecksumMismatch { expected, actual };
+            return Err(io::Error::from(e).into());
+        }
+        Ok(())
+    }
+
+    /// Verifies the checksum if the stream ends right after the `consumed`
+    /// bytes, reading ahead if they are all the buffer holds
+    fn verify_checksum_at_end(&mut self, buf: &mut dyn Buffered, consumed: usize) -> Result<()> {
+        if self.checksum.is_none() {
+            return Ok(());
+        }
+        if buf.data().len() <= consumed {
+            buf.grow(IVF_FRAME_HEADER_LEN);
+            if buf.fill_buf()?.len() <= consumed {
+                return self.verify_checksum();
             }
-            Err(e) => {
-                error!("error reading headers: {:?}", e);
-                Err(Error::InvalidData)
+        }
+        Ok(())
+    }
+
+    /// Asks for `needed` header bytes when only `buffered` are available
+    fn header_incomplete(&mut self, buffered: usize, needed: usize) -> Result<SeekFrom> {
+        // the header may arrive in pieces on a stream, but the context
+        // retries without checking for EOF
+        if self.header_buffered.replace(buffered) == Some(buffered) {
+            error!("stream ended after {} header bytes", buffered);
+            return Err(io::Error::from(IvfError::TruncatedHeader).into());
+        }
+        debug!("incomplete header, {} bytes buffered", buffered);
+        Err(Error::MoreDataNeeded(needed))
+    }
+
+    /// Checks whether `data` starts with the index trailer rather than a
+    /// frame.
+    ///
+    /// Unless an index was found, the trailer must validate in full.
+    fn at_trailer(&self, data: &[u8]) -> Result<bool> {
+        let len = match trailer_len(data) {
+            Some(len) => len,
+            None => return Ok(false),
+        };
+        if self.index.is_some() {
+            return Ok(true);
+        }
+        // the bytes read as a frame ending before the would-be trailer
+        let frame_len = IVF_FRAME_HEADER_LEN as u64 + get_u32l(&data[0..4]) as u64;
+        if len > frame_len {
+            return Ok(false);
+        }
+        match data.get(..len as usize) {
+            Some(trailer) => Ok(is_trailer(trailer)),
+            None => Err(Error::MoreDataNeeded(len as usize)),
+        }
+    }
+
+    /// Locates the keyframe requested by `set_seek_timestamp` in the index
+    fn seek_target(&mut self, buf: &mut dyn Buffered) -> Result<Option<SeekFrom>> {
+        let (target, index) = match (self.seek_timestamp, &self.index) {
+            (Some(target), Some(index)) => (target, index),
+            _ => return Ok(None),
+        };
+        let found = index
+            .iter()
+            .enumerate()
+            .take_while(|(_, entry)| entry.timestamp <= target)
+            .filter(|(_, entry)| entry.is_key)
+            .last();
+        let (frame_index, entry) = match found {
+            Some(found) => found,
+            None => return Ok(None),
+        };
+
+        debug!("seeking to frame {}: {:?}", frame_index, entry);
+        let offset = buf.stream_position()? + entry.offset;
+        self.frames_parsed = frame_index as u32;
+        if self.checksum.take().is_some() {
+            warn!("seeking past the first frame, the checksum is not verified");
+        }
+        Ok(Some(SeekFrom::Start(offset)))
+    }
+
+    /// Carries 32-bit timestamps over their wraparounds
+    fn unwrap_timestamp(&mut self, timestamp: u64) -> u64 {
+        if !self.narrow_timestamps {
+            return timestamp;
+        }
+        if let Some(last) = self.last_narrow_timestamp {
+            // a decrease by more than half the range is a wrap, not a reset
+            if last > timestamp && last - timestamp > 1 << 31 {
+                self.wrap_offset += 1 << 32;
             }
         }
+        self.last_narrow_timestamp = Some(timestamp);
+        self.wrap_offset + timestamp
     }
 
-    fn read_event(&mut self, buf: &mut dyn Buffered) -> Result<(SeekFrom, Event)> {
+    /// Replaces a timestamp too far from the previous one, returning
+    /// whether it did
+    fn clamp_timestamp(&mut self, timestamp: u64) -> (u64, bool) {
+        let (factor, last) = match (self.clamp_factor, self.last_timestamp) {
+            (Some(factor), Some(last)) => (factor, last),
+            _ => {
+                self.last_timestamp = Some(timestamp);
+                return (timestamp, false);
+            }
+        };
+
+        let mut sorted = self.recent_deltas.iter().copied().collect::<Vec<_>>();
+        sorted.sort_unstable();
+        let median = sorted.get(sorted.len() / 2).copied();
+        let delta = timestamp.checked_sub(last);
+        let (timestamp, clamped) = match (median, delta) {
+            // too little history to tell
+            (_, Some(delta)) if sorted.len() < 2 => {
+                self.recent_deltas.push_back(delta);
+                (timestamp, false)
+            }
+            (Some(median), Some(delta)) if delta as f64 <= median as f64 * factor => {
+                self.recent_deltas.push_back(delta);
+                (timestamp, false)
+            }
+            (median, _) => {
+                let median = median.unwrap_or(1);
+                let clamped = last.saturating_add(median);
+                debug!("clamping timestamp {} to {}", timestamp, clamped);
+                (clamped, true)
+            }
+        };
+        if self.recent_deltas.len() > CLAMP_WINDOW {
+            self.recent_deltas.pop_front();
+        }
+        self.last_timestamp = Some(timestamp);
+
+        (timestamp, clamped)
+    }
+
+    fn packet_from_frame(&mut self, frame: IvfFrame, frame_index: u32) -> Packet {
+        let (timestamp, mut is_key) = self.frame_timestamp(&frame);
+        let timestamp = self.unwrap_timestamp(timestamp);
+        let (timestamp, clamped) = if self.signed_timestamps {
+            // offset binary orders the signed values as the clamping expects
+            let (timestamp, clamped) = self.clamp_timestamp(timestamp ^ SIGN_BIT);
+            (timestamp ^ SIGN_BIT, clamped)
+        } else {
+            self.clamp_timestamp(timestamp)
+        };
+        let indexed = self
+            .index
+            .as_ref()
+            .and_then(|index| index.get(frame_index as usize));
+        if let Some(entry) = indexed {
+            is_key = entry.is_key;
+        } else if !self.keyframe_in_timestamp_msb {
+            is_key = match self.keyframe_policy {
+                KeyframePolicy::AllFalse => false,
+                KeyframePolicy::FirstOnly => frame_index == 0,
+                KeyframePolicy::Detect => self
+                    .header
+                    .as_ref()
+                    .map_or(false, |header| is_keyframe(header.codec, &frame.data)),
+            };
+        }
+
+        let is_corrupted = clamped
+            || match self.header.as_ref() {
+                Some(header) if self.detect_corruption => {
+                    looks_corrupted(header.codec, &frame.data)
+                }
+                _ => false,
+            };
+
+        let metadata = self
+            .sidecar
+            .as_ref()
+            .and_then(|sidecar| sidecar.get(&frame_index))
+            .map(|metadata| {
+                Arc::new(FrameMetadata(metadata.clone())) as Arc<dyn Any + Send + Sync>
+            });
+
+        Packet {
+            data: frame.data,
+            pos: Some(timestamp as usize),
+            stream_index: 0,
+            t: TimeInfo {
+                pts: Some(timestamp as i64),
+                dts: self.signed_timestamps.then_some(timestamp as i64),
+                timebase: self.header.as_ref().map(IvfHeader::timebase),
+                user_private: metadata,
+                ..Default::default()
+            },
+            is_key,
+            is_corrupted,
+        }
+    }
+
+    /// Describes the stream as currently known
+    fn stream(&self, header: &IvfHeader) -> Stream {
+        let mut st = header.to_stream();
+        st.params.extradata = self.codec_config.clone();
+        st.start = self
+            .start
+            .filter(|&ts| !self.signed_timestamps || ts & SIGN_BIT == 0)
+            .and_then(|ts| to_nanoseconds(ts, header));
+        st.duration = Some(header.frame_count(self.frame_count_width));
+        st
+    }
+
+    /// Returns the updated stream if `frame` carries an AV1 sequence header
+    /// differing from the current one, see `set_stream_updates`
+    fn stream_update(&mut self, frame: &IvfFrame) -> Option<Stream> {
+        let header = self.header.as_ref()?;
+        if !self.stream_updates || header.codec != Codec::AV1 {
+            return None;
+        }
+        let sequence_header = av1_sequence_header(&frame.data)?;
+        match &self.codec_config {
+            Some(current) if current != sequence_header => {}
+            _ => return None,
+        }
+        debug!("new sequence header: {:?}", sequence_header);
+
+        let mut st = self.stream(header);
+        st.params.extradata = Some(sequence_header.to_vec());
+        if let Some((width, height)) = coded_size(Codec::AV1, &frame.data) {
+            st.params.kind = Some(MediaKind::Video(VideoInfo {
+                width: width as usize,
+                height: height as usize,
+                format: None,
+            }));
+        }
+        self.codec_config = Some(sequence_header.to_vec());
+        Some(st)
+    }
+
+    /// Gathers the stream properties found in the frames
+    fn inspect_frame(&mut self, frame: &IvfFrame) {
+        if self.start.is_none() {
+            self.start = Some(self.frame_timestamp(frame).0);
+        }
+        if self.header.as_ref().map(|h| h.codec) == Some(Codec::VP9) {
+            if let Some(size) = vp9_render_size(&frame.data) {
+                self.render_size = Some(size);
+            }
+        }
+        self.capture_codec_config(frame);
+        if let Some(checksum) = self.checksum.as_mut() {
+            checksum.update(&frame.data);
+        }
+    }
+
+    fn copy_payload(&self, data: &[u8]) -> Vec<u8> {
+        match &self.pool {
+            Some(pool) => pool.copy_of(data),
+            None => data.to_vec(),
+        }
+    }
+
+    /// Emits the buffered start of a frame, see `set_partial_frames`
+    fn first_piece(&mut self, buf: &mut dyn Buffered) -> Option<(SeekFrom, Event)> {
+        let (payload, (size, timestamp)) = tuple((parse_u32, parse_u64))(buf.data()).ok()?;
+        if payload.is_empty() {
+            return None;
+        }
+        debug!(
+            "found partial frame with size: {}, {} bytes buffered",
+            size,
+            payload.len()
+        );
+
+        let frame = IvfFrame {
+            size,
+            timestamp,
+            data: self.copy_payload(payload),
+        };
+        self.inspect_frame(&frame);
+        let token = PartialFrame {
+            frame_index: self.frames_parsed,
+            offset: 0,
+            size,
+        };
+        let mut pkt = self.packet_from_frame(frame, self.frames_parsed);
+        pkt.t.user_private = Some(Arc::new(token));
+        self.partial = Some(PartialState {
+            token: PartialFrame {
+                offset: payload.len() as u32,
+                ..token
+            },
+            template: Packet {
+                data: Vec::new(),
+                ..pkt.clone()
+            },
+        });
+
+        let consumed = IVF_FRAME_HEADER_LEN + payload.len();
+        Some((SeekFrom::Current(consumed as i64), self.flush_pending(pkt)))
+    }
+
+    /// Emits the following piece of the frame being split
+    fn next_piece(&mut self, buf: &mut dyn Buffered) -> Result<(SeekFrom, Event)> {
+        let state = match self.partial.as_mut() {
+            Some(state) => state,
+            None => return Err(Error::InvalidData),
+        };
+        let remaining = (state.token.size - state.token.offset) as usize;
+        let data = &buf.data()[..buf.data().len().min(remaining)];
+        if data.is_empty() {
+            return Err(Error::MoreDataNeeded(1));
+        }
+
+        let mut pkt = state.template.clone();
+        pkt.t.user_private = Some(Arc::new(state.token));
+        state.token.offset += data.len() as u32;
+        if state.token.offset == state.token.size {
+            self.partial = None;
+            self.frames_parsed += 1;
+        }
+        if let Some(checksum) = self.checksum.as_mut() {
+            checksum.update(data);
+        }
+        pkt.data = self.copy_payload(data);
+        let consumed = data.len();
+        if self.partial.is_none() {
+            self.verify_checksum_at_end(buf, consumed)?;
+        }
+
+        Ok((SeekFrom::Current(consumed as i64), Event::NewPacket(pkt)))
+    }
+
+    /// Emits the packet held for its duration before `pkt`
+    fn flush_pending(&mut self, pkt: Packet) -> Event {
+        match self.pending.take() {
+            Some(pending) => {
+                self.queue.push_back(Event::NewPacket(pkt));
+                Event::NewPacket(pending)
+            }
+            None => Event::NewPacket(pkt),
+        }
+    }
+
+    fn next_event(&mut self, buf: &mut dyn Buffered) -> Result<(SeekFrom, Event)> {
         if let Some(event) = self.queue.pop_front() {
             Ok((SeekFrom::Current(0), event))
+        } else if self.partial.is_some() {
+            self.next_piece(buf)
         } else {
-            // check for EOF
-            if buf.data().is_empty() {
-                return Ok((SeekFrom::Current(0), Event::Eof));
+            // nothing buffered, let the context read more or report EOF
+            if buf.data().is_empty() && self.pending.is_none() {
+                return Err(Error::MoreDataNeeded(IVF_FRAME_HEADER_LEN));
+            }
+
+            // check for EOF, the index trailer is not a frame
+            if buf.data().is_empty() || self.at_trailer(buf.data())? {
+                let event = match self.pending.take() {
+                    Some(pkt) => Event::NewPacket(pkt),
+                    None => Event::Eof,
+                };
+                return Ok((SeekFrom::Current(0), event));
             }
 
             // feed with more stuff
-            match ivf_frame(buf.data()) {
-                Ok((input, frame)) => {
-                    debug!("found frame with size: {}\tpos: {}", frame.size, frame.pos);
-
-                    let pkt = Packet {
-                        data: frame.data,
-                        pos: Some(frame.pos as usize),
-                        stream_index: 0,
-                        t: TimeInfo::default(),
-                        is_key: false,
-                        is_corrupted: false,
+            match ivf_frame_ref(buf.data()) {
+                Ok((input, (size, timestamp, data))) => {
+                    let frame = IvfFrame {
+                        size,
+                        timestamp,
+                        data: self.copy_payload(data),
+                    };
+                    debug!(
+                        "found frame with size: {}\ttimestamp: {}",
+                        frame.size, frame.timestamp
+                    );
+
+                    let update = self.stream_update(&frame);
+                    self.inspect_frame(&frame);
+                    let pkt = self.packet_from_frame(frame, self.frames_parsed);
+                    self.frames_parsed += 1;
+                    let mut event = if self.compute_durations {
+                        let next_pts = pkt.t.pts;
+                        match self.pending.replace(pkt) {
+                            Some(mut pkt) => {
+                                pkt.t.duration = frame_duration(pkt.t.pts, next_pts);
+                                Event::NewPacket(pkt)
+                            }
+                            None => Event::Continue,
+                        }
+                    } else {
+                        Event::NewPacket(pkt)
                     };
 
-                    Ok((
-                        SeekFrom::Current(buf.data().offset(input) as i64),
-                        Event::NewPacket(pkt),
-                    ))
+                    if let Some(st) = update {
+                        // right before the packet of the frame
+                        if self.queue_full() {
+                            warn!("event queue full, dropping stream update {:?}", st);
+                        } else if self.compute_durations {
+                            self.queue.push_back(Event::NewStream(st));
+                        } else {
+                            self.queue.push_back(event);
+                            event = Event::NewStream(st);
+                        }
+                    }
+
+                    let consumed = buf.data().offset(input);
+                    self.verify_checksum_at_end(buf, consumed)?;
+
+                    Ok((SeekFrom::Current(consumed as i64), event))
                 }
                 Err(Err::Incomplete(needed)) => {
+                    if self.partial_frames {
+                        if let Some(piece) = self.first_piece(buf) {
+                            return Ok(piece);
+                        }
+                    }
                     let sz = match needed {
                         Needed::Size(size) => buf.data().len() + size.get(),
                         Needed::Unknown => 1024,
@@ -144,23 +1119,204 @@ impl Demuxer for IvfDemuxer {
     }
 }
 
+/// Reads the index trailer if the source can seek, then moves back to
+/// where it was.
+fn read_index_trailer(buf: &mut dyn Buffered) -> Result<Option<Vec<IndexEntry>>> {
+    let pos = buf.stream_position()?;
+    if buf.seek(SeekFrom::End(0)).is_err() {
+        debug!("unseekable source, no index trailer");
+        return Ok(None);
+    }
+    let index = read_index(&mut &mut *buf);
+    buf.seek(SeekFrom::Start(pos))?;
+    let index = index?;
+    if let Some(index) = &index {
+        debug!("found index trailer with {} entries", index.len());
+    }
+    Ok(index)
+}
+
+/// Duration of a frame given the timestamp of the following one.
+///
+/// libvpx gives no-show alt-ref frames the timestamp of the next shown
+/// frame, so equal timestamps yield a zero duration for the hidden one.
+fn frame_duration(pts: Option<i64>, next_pts: Option<i64>) -> Option<u64> {
+    let delta = next_pts?.checked_sub(pts?)?;
+    u64::try_from(delta).ok()
+}
+
+/// Converts a timestamp in the header timebase to the 1ns stream timebase.
+fn to_nanoseconds(ts: u64, header: &IvfHeader) -> Option<u64> {
+    if header.rate == 0 {
+        return None;
+    }
+    let ns = ts as u128 * header.scale as u128 * 1_000_000_000 / header.rate as u128;
+    u64::try_from(ns).ok()
+}
+
+impl Demuxer for IvfDemuxer {
+    fn read_headers(&mut self, buf: &mut dyn Buffered, info: &mut GlobalInfo) -> Result<SeekFrom> {
+        let options = HeaderOptions {
+            forced_codec: self.forced_codec,
+            lenient: self.lenient,
+            big_endian: self.big_endian,
+        };
+        match ivf_header_with(buf.data(), options) {
+            Ok((_, header))
+                if header.header_len != IVF_HEADER_LEN as u16
+                    && looks_text_mode_corrupted(buf.data()) =>
+            {
+                error!("header corrupted by a text mode transfer: {:?}", header);
+                Err(io::Error::from(IvfError::LikelyTextModeCorruption).into())
+            }
+            Ok((_, header)) if buf.data().len() < header_size(header.header_len) => {
+                self.header_incomplete(buf.data().len(), header_size(header.header_len))
+            }
+            Ok((_, header)) => {
+                debug!("found header: {:?}", header);
+                if header.looks_byte_swapped() {
+                    warn!("the header looks big-endian, see set_assume_big_endian");
+                }
+                let header_len = header_size(header.header_len);
+                self.raw_header = buf.data()[..header_len].to_vec();
+                self.header = Some(header);
+                let mut seek = SeekFrom::Current(header_len as i64);
+                if self.index.is_none() && (self.use_index || self.seek_timestamp.is_some()) {
+                    self.index = read_index_trailer(buf)?;
+                }
+                match self.seek_target(buf)? {
+                    Some(target) => seek = target,
+                    // peek the first frame if it is already buffered
+                    None => {
+                        let input = buf.data().get(header_len..).unwrap_or_default();
+                        if let Ok((_, frame)) = ivf_frame(input) {
+                            self.start = Some(self.frame_timestamp(&frame).0);
+                            self.capture_codec_config(&frame);
+                        }
+                    }
+                }
+                let header = self.header.as_ref().unwrap();
+                info.timebase = Some(header.timebase());
+                info.add_stream(self.stream(header));
+                Ok(seek)
+            }
+            Err(Err::Incomplete(_)) => self.header_incomplete(buf.data().len(), IVF_HEADER_LEN),
+            Err(_) if looks_text_mode_corrupted(buf.data()) => {
+                error!("header corrupted by a text mode transfer");
+                Err(io::Error::from(IvfError::LikelyTextModeCorruption).into())
+            }
+            Err(e) => {
+                error!("error reading headers: {:?}", e);
+                Err(Error::InvalidData)
+            }
+        }
+    }
+
+    fn read_event(&mut self, buf: &mut dyn Buffered) -> Result<(SeekFrom, Event)> {
+        if let (Some(limit), None) = (self.frame_limit, &self.partial) {
+            if self.frames_emitted as usize >= limit {
+                return Ok((SeekFrom::Current(0), Event::Eof));
+            }
+        }
+        let (seek, event) = self.next_event(buf)?;
+        let starts_frame = match &event {
+            Event::NewPacket(pkt) => PartialFrame::of(pkt).map_or(true, |token| token.offset == 0),
+            _ => false,
+        };
+        if starts_frame {
+            let declared = match self.header.as_ref() {
+                Some(header) if self.enforce_frame_count => {
+                    u32::try_from(header.frame_count(self.frame_count_width)).ok()
+                }
+                _ => None,
+            };
+            for limit in [self.max_frames, declared.filter(|&nframe| nframe > 0)] {
+                match limit {
+                    Some(limit) if self.frames_emitted >= limit => {
+                        return Err(io::Error::from(IvfError::TooManyFrames(limit)).into());
+                    }
+                    _ => {}
+                }
+            }
+            self.frames_emitted += 1;
+        }
+        if matches!(event, Event::Eof) {
+            self.verify_checksum()?;
+        }
+        Ok((seek, event))
+    }
+}
+
+/// Dispatches a demuxer event, handing packets over to `on_packet`.
+///
+/// Returns `ControlFlow::Break` once the end of the stream is reached and
+/// `ControlFlow::Continue` for every other event, including the ones that
+/// `av-format` might add in the future, so callers do not need a catch-all
+/// arm of their own.
+pub fn handle_event(e: Event, mut on_packet: impl FnMut(Packet)) -> ControlFlow<()> {
+    match e {
+        Event::NewPacket(pkt) => {
+            on_packet(pkt);
+            ControlFlow::Continue(())
+        }
+        Event::NewStream(st) => {
+            debug!("new stream: {:?}", st);
+            ControlFlow::Continue(())
+        }
+        Event::MoreDataNeeded(sz) => {
+            debug!("more data needed: {} bytes", sz);
+            ControlFlow::Continue(())
+        }
+        Event::Continue => ControlFlow::Continue(()),
+        Event::Eof => ControlFlow::Break(()),
+        e => {
+            debug!("ignoring event: {:?}", e);
+            ControlFlow::Continue(())
+        }
+    }
+}
+
+/// Returns the `Incomplete` error for a field of `len` bytes, if needed
+fn need(input: &[u8], len: usize) -> IResult<&[u8], ()> {
+    match len.checked_sub(input.len()) {
+        Some(missing) if missing > 0 => Err(Err::Incomplete(Needed::new(missing))),
+        _ => Ok((input, ())),
+    }
+}
+
 /// u16 nom help function that maps to av-bitstream
 fn parse_u16(input: &[u8]) -> IResult<&[u8], u16> {
+    need(input, 2)?;
     Ok((&input[2..], get_u16l(&input[0..2])))
 }
 
 /// u32 nom help function that maps to av-bitstream
 fn parse_u32(input: &[u8]) -> IResult<&[u8], u32> {
+    need(input, 4)?;
     Ok((&input[4..], get_u32l(&input[0..4])))
 }
 
+/// Big-endian counterpart of `parse_u16`
+fn parse_u16b(input: &[u8]) -> IResult<&[u8], u16> {
+    need(input, 2)?;
+    Ok((&input[2..], get_u16b(&input[0..2])))
+}
+
+/// Big-endian counterpart of `parse_u32`
+fn parse_u32b(input: &[u8]) -> IResult<&[u8], u32> {
+    need(input, 4)?;
+    Ok((&input[4..], get_u32b(&input[0..4])))
+}
+
 /// u64 nom help function that maps to av-bitstream
 fn parse_u64(input: &[u8]) -> IResult<&[u8], u64> {
+    need(input, 8)?;
     Ok((&input[8..], get_u64l(&input[0..8])))
 }
 
 /// use ErrorKind::Tag that could be a bit confusing
 fn parse_codec(input: &[u8]) -> IResult<&[u8], Codec> {
+    need(input, 4)?;
     let codec = match &input[0..4] {
         b"VP80" => Codec::VP8,
         b"VP90" => Codec::VP9,
@@ -176,55 +1332,150 @@ fn parse_codec(input: &[u8]) -> IResult<&[u8], Codec> {
     Ok((&input[4..], codec))
 }
 
-// TODO: validate values
-fn ivf_header(input: &[u8]) -> IResult<&[u8], IvfHeader> {
-    tuple((
-        tag("DKIF"),
-        parse_u16,
-        parse_u16,
-        parse_codec,
-        parse_u16,
-        parse_u16,
-        parse_u32,
-        parse_u32,
-        parse_u32,
-        take(4usize),
-    ))(input)
-    .map(
-        |(input, (_tag, version, _length, codec, width, height, rate, scale, nframe, _))| {
-            (
-                input,
-                IvfHeader {
-                    version,
-                    width,
-                    height,
-                    rate,
-                    scale,
-                    codec,
-                    nframe,
-                },
-            )
+/// Accepts any FourCC, reporting `codec` in its place
+fn forced_codec(codec: Codec) -> impl Fn(&[u8]) -> IResult<&[u8], Codec> {
+    move |input| {
+        let (input, _) = take(4usize)(input)?;
+        Ok((input, codec))
+    }
+}
+
+pub(crate) fn ivf_header(input: &[u8]) -> IResult<&[u8], IvfHeader> {
+    ivf_header_with(input, HeaderOptions::default())
+}
+
+/// Like `ivf_header`, also accepting the legacy `SHORT_HEADER_LENS`
+pub(crate) fn ivf_header_lenient(input: &[u8]) -> IResult<&[u8], IvfHeader> {
+    let options = HeaderOptions {
+        lenient: true,
+        ..HeaderOptions::default()
+    };
+    ivf_header_with(input, options)
+}
+
+/// Returns `true` if `data` starts with a valid header once the CR bytes
+/// preceding LF bytes are dropped, as transfers in text mode insert them.
+fn looks_text_mode_corrupted(data: &[u8]) -> bool {
+    let data = &data[..data.len().min(2 * IVF_HEADER_LEN)];
+    if !data.starts_with(b"DKIF") || !data.windows(2).any(|pair| pair == b"\r\n") {
+        return false;
+    }
+    let fixed = data
+        .iter()
+        .enumerate()
+        .filter(|&(i, &b)| !(b == b'\r' && data.get(i + 1) == Some(&b'\n')))
+        .map(|(_, &b)| b)
+        .collect::<Vec<_>>();
+    matches!(ivf_header(&fixed), Ok((_, header)) if header.header_len == IVF_HEADER_LEN as u16)
+}
+
+/// Deviations from the specification accepted while parsing the header
+#[derive(Clone, Copy, Default)]
+struct HeaderOptions {
+    forced_codec: Option<Codec>,
+    lenient: bool,
+    big_endian: bool,
+}
+
+/// Header lengths written by legacy tools that omit the reserved field
+const SHORT_HEADER_LENS: &[u16] = &[28];
+
+/// Number of bytes spanned by a header declaring `header_len`, the first
+/// frame follows them.
+///
+/// Lengths past 32 bytes leave room for extra fields, shorter ones other
+/// than `SHORT_HEADER_LENS` still hold the reserved field. A length
+/// reading 32 once byte-swapped is taken as such, see
+/// `IvfHeader::looks_byte_swapped`.
+pub(crate) fn header_size(header_len: u16) -> usize {
+    if SHORT_HEADER_LENS.contains(&header_len) {
+        header_len as usize
+    } else if header_len.swap_bytes() == IVF_HEADER_LEN as u16 {
+        IVF_HEADER_LEN
+    } else {
+        (header_len as usize).max(IVF_HEADER_LEN)
+    }
+}
+
+// TODO: validate values
+fn ivf_header_with(input: &[u8], options: HeaderOptions) -> IResult<&[u8], IvfHeader> {
+    let codec = move |input| match options.forced_codec {
+        Some(codec) => forced_codec(codec)(input),
+        None => parse_codec(input),
+    };
+    let parse_u16 = move |input| {
+        if options.big_endian {
+            parse_u16b(input)
+        } else {
+            parse_u16(input)
+        }
+    };
+    let parse_u32 = move |input| {
+        if options.big_endian {
+            parse_u32b(input)
+        } else {
+            parse_u32(input)
+        }
+    };
+
+    let (input, (_tag, version, header_len, codec, width, height, rate, scale, nframe)) =
+        tuple((
+            tag("DKIF"),
+            parse_u16,
+            parse_u16,
+            codec,
+            parse_u16,
+            parse_u16,
+            parse_u32,
+            parse_u32,
+            parse_u32,
+        ))(input)?;
+
+    let (input, reserved) = if SHORT_HEADER_LENS.contains(&header_len) {
+        if !options.lenient {
+            return Err(Err::Error(error_position!(input, ErrorKind::Verify)));
+        }
+        // the frames start right away
+        (input, 0)
+    } else {
+        parse_u32(input)?
+    };
+
+    Ok((
+        input,
+        IvfHeader {
+            version,
+            header_len,
+            width,
+            height,
+            rate,
+            scale,
+            codec,
+            nframe,
+            reserved,
         },
-    )
+    ))
 }
 
 // (frame_size > 256 * 1024 * 1024)
 fn ivf_frame(input: &[u8]) -> IResult<&[u8], IvfFrame> {
-    tuple((parse_u32, parse_u64))(input)
-        .and_then(|(input, (size, pos))| {
-            let (input, data) = take(size)(input)?;
-            Ok((input, (size, pos, data)))
-        })
-        .map(|(input, (size, pos, data))| {
-            (
-                input,
-                IvfFrame {
-                    size,
-                    pos,
-                    data: data.to_owned(),
-                },
-            )
-        })
+    ivf_frame_ref(input).map(|(input, (size, timestamp, data))| {
+        (
+            input,
+            IvfFrame {
+                size,
+                timestamp,
+                data: data.to_owned(),
+            },
+        )
+    })
+}
+
+/// Parses a frame as `(size, timestamp, payload)`, borrowing the payload
+fn ivf_frame_ref(input: &[u8]) -> IResult<&[u8], (u32, u64, &[u8])> {
+    let (input, (size, timestamp)) = tuple((parse_u32, parse_u64))(input)?;
+    let (input, data) = take(size)(input)?;
+    Ok((input, (size, timestamp, data)))
 }
 
 struct Des {
@@ -241,13 +1492,39 @@ impl Descriptor for Des {
         &self.d
     }
     fn probe(&self, data: &[u8]) -> u8 {
-        match ivf_header(&data[..=32]) {
+        // ivf_header never looks past the 32 bytes of the header
+        match ivf_header(data) {
             Ok(_) => 32,
             _ => 0,
         }
     }
 }
 
+/// Like `IVF_DESC.probe`, also weighing the file extension `ext`, with or
+/// without its leading dot.
+///
+/// A valid header scores 32, raised to 48 if the extension is `ivf` or
+/// names a codec carried in IVF, and to 64 if it names the codec of the
+/// header. The extension alone never scores.
+pub fn probe_with_hint(data: &[u8], ext: Option<&str>) -> u8 {
+    let header = match ivf_header(data) {
+        Ok((_, header)) => header,
+        _ => return 0,
+    };
+    let ext = match ext {
+        Some(ext) => ext.trim_start_matches('.').to_ascii_lowercase(),
+        None => return 32,
+    };
+
+    if ext == String::from(header.codec) {
+        64
+    } else if ext == "ivf" || IVF_DESC.describe().extensions.contains(&ext.as_str()) {
+        48
+    } else {
+        32
+    }
+}
+
 /// used by av context
 pub const IVF_DESC: &dyn Descriptor<OutputDemuxer = IvfDemuxer> = &Des {
     d: Descr {
@@ -324,4 +1601,803 @@ mod tests {
             }
         }
     }
+
+    #[test]
+    fn demux_zero_size_frame() {
+        let data = crate::test_util::ivf(b"AV01", &[(0, &[0x12, 0x00]), (1, &[]), (2, &[0x12])]);
+        let mut demuxer = Context::new(IvfDemuxer::new(), AccReader::new(Cursor::new(data)));
+        demuxer.read_headers().unwrap();
+
+        let mut sizes = Vec::new();
+        while let Event::NewPacket(pkt) = demuxer.read_event().unwrap() {
+            assert!(!pkt.is_corrupted);
+            sizes.push(pkt.data.len());
+        }
+
+        assert_eq!(sizes, [2, 0, 1]);
+    }
+
+    #[test]
+    fn detect_corruption() {
+        let mut data = IVF.to_vec();
+        // set the forbidden bit in the first OBU of the second frame
+        let second = 32 + 12 + get_u32l(&data[32..36]) as usize;
+        data[second + 12] |= 0x80;
+
+        let mut ivf_demuxer = IvfDemuxer::new();
+        ivf_demuxer.set_detect_corruption(true);
+        let mut demuxer = Context::new(ivf_demuxer, AccReader::new(Cursor::new(data)));
+        demuxer.read_headers().unwrap();
+
+        let mut corrupted = Vec::new();
+        while let Event::NewPacket(pkt) = demuxer.read_event().unwrap() {
+            corrupted.push(pkt.is_corrupted);
+        }
+
+        assert_eq!(corrupted.len(), 25);
+        assert_eq!(corrupted.iter().filter(|&&c| c).count(), 1);
+        assert!(corrupted[1]);
+    }
+
+    #[test]
+    fn altref_durations() {
+        let frames: [(u64, &[u8]); 5] = [
+            (0, &[0x82, 0x49, 0x83, 0x42]),
+            (1, &[0x86, 0x00]),
+            (1, &[0x86, 0x00]),
+            (2, &[0x86, 0x00]),
+            (3, &[0x86, 0x00]),
+        ];
+        let data = crate::test_util::ivf(b"VP90", &frames);
+
+        let mut ivf_demuxer = IvfDemuxer::new();
+        ivf_demuxer.set_compute_durations(true);
+        let mut demuxer = Context::new(ivf_demuxer, AccReader::new(Cursor::new(data)));
+        demuxer.read_headers().unwrap();
+
+        let mut durations = Vec::new();
+        loop {
+            match demuxer.read_event().unwrap() {
+                Event::NewPacket(pkt) => durations.push(pkt.t.duration),
+                Event::Continue => continue,
+                _ => break,
+            }
+        }
+
+        assert_eq!(durations, [Some(1), Some(0), Some(1), Some(1), None]);
+    }
+
+    #[test]
+    fn progress() {
+        let mut demuxer = Context::new(IvfDemuxer::new(), AccReader::new(Cursor::new(IVF)));
+        assert_eq!(demuxer.demuxer().progress(), None);
+        demuxer.read_headers().unwrap();
+        assert_eq!(demuxer.demuxer().progress(), Some(0.0));
+
+        let mut last = 0.0;
+        while let Event::NewPacket(_) = demuxer.read_event().unwrap() {
+            let progress = demuxer.demuxer().progress().unwrap();
+            assert!(progress > last);
+            last = progress;
+        }
+        assert_eq!(last, 1.0);
+    }
+
+    #[test]
+    fn start_timestamp() {
+        use std::io::BufRead;
+
+        let data = crate::test_util::ivf(b"VP80", &[(15, &[0x50]), (16, &[0x51])]);
+        let mut demuxer = Context::new(IvfDemuxer::new(), AccReader::new(Cursor::new(data)));
+        demuxer.read_headers().unwrap();
+        assert_eq!(demuxer.demuxer().start_timestamp(), Some(15));
+        assert_eq!(demuxer.info.streams[0].start, Some(500_000_000));
+
+        // nothing past the header is buffered
+        let data = crate::test_util::ivf(b"VP80", &[(15, &[0x50])]);
+        let mut demuxer = IvfDemuxer::new();
+        let mut info = GlobalInfo {
+            duration: None,
+            timebase: None,
+            streams: Vec::new(),
+        };
+        let mut buf = AccReader::new(Cursor::new(data[..32].to_vec()));
+        buf.fill_buf().unwrap();
+        demuxer.read_headers(&mut buf, &mut info).unwrap();
+        assert_eq!(demuxer.start_timestamp(), None);
+        assert_eq!(info.streams[0].start, None);
+    }
+
+    #[test]
+    fn to_global_info() {
+        let data = crate::test_util::ivf(b"VP80", &[(0, &[0x50]), (1, &[0x51])]);
+        let mut demuxer = Context::new(IvfDemuxer::new(), AccReader::new(Cursor::new(data)));
+        demuxer.read_headers().unwrap();
+        let (_, header) = ivf_header(demuxer.demuxer().raw_header_bytes().unwrap()).unwrap();
+
+        let info = header.to_global_info();
+        assert_eq!(info.timebase, demuxer.info.timebase);
+        assert_eq!(info.streams.len(), 1);
+        let (built, read) = (&info.streams[0], &demuxer.info.streams[0]);
+        assert_eq!(built.params, read.params);
+        assert_eq!((built.id, built.index), (read.id, read.index));
+        assert_eq!(built.timebase, read.timebase);
+        assert_eq!(built.duration, read.duration);
+        assert_eq!(built.start, None);
+    }
+
+    #[test]
+    fn timestamp_is_byte_offset() {
+        let data = crate::test_util::ivf(
+            b"VP80",
+            &[
+                (32, &[0x50, 0, 0]),
+                (47, &[0x51, 0, 0]),
+                (62, &[0x51, 0, 0]),
+            ],
+        );
+        let mut ivf_demuxer = IvfDemuxer::new();
+        ivf_demuxer.set_timestamp_is_byte_offset(true);
+        let mut demuxer = Context::new(ivf_demuxer, AccReader::new(Cursor::new(data)));
+        demuxer.read_headers().unwrap();
+        assert_eq!(demuxer.demuxer().start_timestamp(), Some(0));
+
+        let mut pts = Vec::new();
+        while let Ok(Event::NewPacket(pkt)) = demuxer.read_event() {
+            pts.push(pkt.t.pts.unwrap());
+        }
+        assert_eq!(pts, [0, 1, 2]);
+    }
+
+    #[test]
+    fn text_mode_corruption() {
+        let mut data = crate::test_util::ivf(b"VP80", &[(0, &[0x50, 0x0a, 0x00])]);
+        data[4] = b'\n';
+        data.insert(4, b'\r');
+
+        let mut demuxer = Context::new(IvfDemuxer::new(), AccReader::new(Cursor::new(data)));
+        match demuxer.read_headers() {
+            Err(Error::Io(e)) => {
+                let inner = e.get_ref().unwrap().downcast_ref::<IvfError>();
+                assert!(matches!(inner, Some(IvfError::LikelyTextModeCorruption)));
+            }
+            r => panic!("unexpected result: {:?}", r.map(|_| ())),
+        }
+    }
+
+    #[test]
+    fn partial_frames() {
+        let key = (0..40)
+            .map(|i| if i == 0 { 0x50 } else { i })
+            .collect::<Vec<u8>>();
+        let data = crate::test_util::ivf(b"VP80", &[(0, &key), (1, &[0x51, 0x01, 0x00])]);
+
+        let mut ivf_demuxer = IvfDemuxer::new();
+        ivf_demuxer.set_partial_frames(true);
+        ivf_demuxer.set_keyframe_policy(KeyframePolicy::Detect);
+        // only the frame header and half of the first payload fit at first
+        let reader = AccReader::with_capacity(64, Cursor::new(data));
+        let mut demuxer = Context::new(ivf_demuxer, reader);
+        demuxer.read_headers().unwrap();
+
+        let mut pieces = Vec::new();
+        while let Event::NewPacket(pkt) = demuxer.read_event().unwrap() {
+            let token = PartialFrame::of(&pkt).copied();
+            let last = token.map(|token| token.is_last(&pkt));
+            pieces.push((token.map(|token| token.offset), last, pkt.is_key, pkt.data));
+        }
+
+        assert_eq!(pieces.len(), 3);
+        assert_eq!(pieces[0], (Some(0), Some(false), true, key[..20].to_vec()));
+        assert_eq!(pieces[1], (Some(20), Some(true), true, key[20..].to_vec()));
+        assert_eq!(pieces[2], (None, None, false, vec![0x51, 0x01, 0x00]));
+    }
+
+    #[test]
+    fn stream_updates() {
+        let first = &IVF[32 + 12..32 + 12 + 0x952];
+        let mut switched = first.to_vec();
+        // signal a narrower maximum frame size
+        assert_eq!(switched[9], 0xff);
+        switched[9] = 0xfb;
+        let data = crate::test_util::ivf(b"AV01", &[(0, first), (1, first), (2, &switched)]);
+
+        let mut ivf_demuxer = IvfDemuxer::new();
+        ivf_demuxer.set_stream_updates(true);
+        let mut demuxer = Context::new(ivf_demuxer, AccReader::new(Cursor::new(data)));
+        demuxer.read_headers().unwrap();
+
+        let mut events = Vec::new();
+        loop {
+            match demuxer.read_event().unwrap() {
+                Event::NewPacket(pkt) => events.push(format!("packet {}", pkt.t.pts.unwrap())),
+                Event::NewStream(st) => {
+                    assert_eq!(st.params.extradata.as_deref(), Some(&switched[2..15]));
+                    match st.params.kind {
+                        Some(MediaKind::Video(info)) => {
+                            events.push(format!("stream {}x{}", info.width, info.height))
+                        }
+                        kind => panic!("unexpected kind: {:?}", kind),
+                    }
+                }
+                Event::Eof => break,
+                event => panic!("unexpected event: {:?}", event),
+            }
+        }
+        assert_eq!(
+            events,
+            ["packet 0", "packet 1", "stream 319x240", "packet 2"]
+        );
+        assert_eq!(demuxer.info.streams.len(), 2);
+    }
+
+    #[test]
+    fn queue_holds_one_event() {
+        let first = &IVF[32 + 12..32 + 12 + 0x952];
+        let mut switched = first.to_vec();
+        switched[9] = 0xfb;
+        let data = crate::test_util::ivf(
+            b"AV01",
+            &[(0, first), (1, &switched), (2, first), (3, &switched)],
+        );
+
+        for (compute_durations, partial_frames) in [(false, false), (true, false), (true, true)] {
+            let mut ivf_demuxer = IvfDemuxer::new();
+            ivf_demuxer.set_stream_updates(true);
+            ivf_demuxer.set_compute_durations(compute_durations);
+            ivf_demuxer.set_partial_frames(partial_frames);
+            let reader = AccReader::with_capacity(1024, Cursor::new(&data));
+            let mut demuxer = Context::new(ivf_demuxer, reader);
+            demuxer.read_headers().unwrap();
+
+            let mut streams = 0;
+            loop {
+                let event = demuxer.read_event().unwrap();
+                assert!(demuxer.demuxer().queue.len() <= 1);
+                match event {
+                    Event::NewStream(_) => streams += 1,
+                    Event::Eof => break,
+                    _ => {}
+                }
+            }
+            // the frames exceed the buffer, partial ones are not inspected
+            assert_eq!(streams, if partial_frames { 0 } else { 3 });
+        }
+    }
+
+    #[test]
+    fn max_queued_events() {
+        let first = &IVF[32 + 12..32 + 12 + 0x952];
+        let mut switched = first.to_vec();
+        switched[9] = 0xfb;
+        let frames = [(0, first), (1, &switched[..]), (2, first), (3, &switched)];
+        let data = crate::test_util::ivf(b"AV01", &frames);
+
+        for compute_durations in [false, true] {
+            let mut ivf_demuxer = IvfDemuxer::new();
+            ivf_demuxer.set_stream_updates(true);
+            ivf_demuxer.set_compute_durations(compute_durations);
+            ivf_demuxer.set_max_queued_events(0);
+            let mut demuxer = Context::new(ivf_demuxer, AccReader::new(Cursor::new(&data)));
+            demuxer.read_headers().unwrap();
+
+            // a consumer pulling one event at a time, checking the backlog
+            let mut packets = Vec::new();
+            loop {
+                let event = demuxer.read_event().unwrap();
+                assert!(demuxer.demuxer().queue.is_empty());
+                match event {
+                    Event::NewPacket(pkt) => packets.push(pkt.t.pts.unwrap()),
+                    Event::NewStream(_) => panic!("stream update not dropped"),
+                    Event::Eof => break,
+                    _ => {}
+                }
+            }
+            assert_eq!(packets, [0, 1, 2, 3]);
+        }
+    }
+
+    #[test]
+    fn temporal_delimiter_only() {
+        let data = crate::test_util::ivf(b"AV01", &[(0, &[0x12, 0x00])]);
+        let mut ivf_demuxer = IvfDemuxer::new();
+        ivf_demuxer.set_keyframe_policy(KeyframePolicy::Detect);
+        ivf_demuxer.set_detect_corruption(true);
+        let mut demuxer = Context::new(ivf_demuxer, AccReader::new(Cursor::new(data)));
+        demuxer.read_headers().unwrap();
+
+        match demuxer.read_event().unwrap() {
+            Event::NewPacket(pkt) => {
+                assert_eq!(pkt.data, [0x12, 0x00]);
+                assert!(!pkt.is_key);
+                assert!(!pkt.is_corrupted);
+            }
+            event => panic!("unexpected event: {:?}", event),
+        }
+    }
+
+    #[test]
+    fn signed_timestamps() {
+        let frames = [-2i64, -1, 0, 1]
+            .iter()
+            .map(|&ts| (ts as u64, &[0x51u8][..]))
+            .collect::<Vec<_>>();
+        let data = crate::test_util::ivf(b"VP80", &frames);
+
+        let demux = |signed| {
+            let mut ivf_demuxer = IvfDemuxer::new();
+            ivf_demuxer.set_signed_timestamps(signed);
+            ivf_demuxer.set_timestamp_clamping(Some(2.0));
+            let mut demuxer = Context::new(ivf_demuxer, AccReader::new(Cursor::new(&data)));
+            demuxer.read_headers().unwrap();
+            let mut timestamps = Vec::new();
+            while let Event::NewPacket(pkt) = demuxer.read_event().unwrap() {
+                assert!(!signed || pkt.t.dts == pkt.t.pts);
+                timestamps.push((pkt.t.pts.unwrap(), pkt.is_corrupted));
+            }
+            (demuxer.info.streams[0].start, timestamps)
+        };
+
+        let (start, timestamps) = demux(true);
+        assert_eq!(start, None);
+        assert_eq!(
+            timestamps,
+            [(-2, false), (-1, false), (0, false), (1, false)]
+        );
+
+        // read as unsigned, the stream goes back from 2^64 - 1 to 0
+        let (_, timestamps) = demux(false);
+        assert!(timestamps[2].1);
+    }
+
+    #[test]
+    fn frame_limit() {
+        let mut ivf_demuxer = IvfDemuxer::new();
+        ivf_demuxer.set_frame_limit(Some(5));
+        let mut demuxer = Context::new(ivf_demuxer, AccReader::new(Cursor::new(IVF)));
+        demuxer.read_headers().unwrap();
+
+        for _ in 0..5 {
+            assert!(matches!(demuxer.read_event(), Ok(Event::NewPacket(_))));
+        }
+        assert!(matches!(demuxer.read_event(), Ok(Event::Eof)));
+    }
+
+    #[test]
+    fn max_frames() {
+        let mut ivf_demuxer = IvfDemuxer::new();
+        ivf_demuxer.set_max_frames(Some(10));
+        let mut demuxer = Context::new(ivf_demuxer, AccReader::new(Cursor::new(IVF)));
+        demuxer.read_headers().unwrap();
+
+        for _ in 0..10 {
+            assert!(matches!(demuxer.read_event(), Ok(Event::NewPacket(_))));
+        }
+        match demuxer.read_event() {
+            Err(Error::Io(e)) => {
+                let inner = e.get_ref().unwrap().downcast_ref::<IvfError>();
+                assert!(matches!(inner, Some(IvfError::TooManyFrames(10))));
+            }
+            r => panic!("unexpected result: {:?}", r),
+        }
+    }
+
+    #[test]
+    fn raw_header_bytes() {
+        let mut demuxer = Context::new(IvfDemuxer::new(), AccReader::new(Cursor::new(IVF)));
+        assert_eq!(demuxer.demuxer().raw_header_bytes(), None);
+        demuxer.read_headers().unwrap();
+
+        let raw = demuxer.demuxer().raw_header_bytes().unwrap();
+        assert_eq!(raw, &IVF[..32]);
+        let (_, header) = ivf_header(raw).unwrap();
+        assert!(header.eq_exact(demuxer.demuxer().header.as_ref().unwrap()));
+    }
+
+    #[test]
+    fn force_codec() {
+        let mut data = IVF.to_vec();
+        data[8..12].copy_from_slice(b"XAV1");
+        // set the forbidden bit in the first OBU of the second frame
+        let second = 32 + 12 + get_u32l(&data[32..36]) as usize;
+        data[second + 12] |= 0x80;
+
+        let mut demuxer = Context::new(IvfDemuxer::new(), AccReader::new(Cursor::new(&data)));
+        assert!(demuxer.read_headers().is_err());
+
+        let mut ivf_demuxer = IvfDemuxer::new();
+        ivf_demuxer.force_codec(Codec::AV1);
+        ivf_demuxer.set_detect_corruption(true);
+        let mut demuxer = Context::new(ivf_demuxer, AccReader::new(Cursor::new(&data)));
+        demuxer.read_headers().unwrap();
+        assert_eq!(
+            demuxer.info.streams[0].params.codec_id.as_deref(),
+            Some("av1")
+        );
+
+        let mut corrupted = Vec::new();
+        while let Event::NewPacket(pkt) = demuxer.read_event().unwrap() {
+            corrupted.push(pkt.is_corrupted);
+        }
+        assert_eq!(corrupted.len(), 25);
+        assert_eq!(corrupted.iter().filter(|&&c| c).count(), 1);
+        assert!(corrupted[1]);
+    }
+
+    #[test]
+    fn incremental_header() {
+        use std::io::{BufRead, Read, Seek};
+
+        /// Hands out at most 4 bytes per read
+        struct Chunked(Cursor<&'static [u8]>);
+
+        impl Read for Chunked {
+            fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
+                let len = buf.len().min(4);
+                self.0.read(&mut buf[..len])
+            }
+        }
+
+        impl Seek for Chunked {
+            fn seek(&mut self, pos: SeekFrom) -> std::io::Result<u64> {
+                self.0.seek(pos)
+            }
+        }
+
+        let mut buf = AccReader::with_capacity(4, Chunked(Cursor::new(IVF)));
+        let mut info = GlobalInfo {
+            duration: None,
+            timebase: None,
+            streams: Vec::new(),
+        };
+        buf.fill_buf().unwrap();
+        let mut demuxer = IvfDemuxer::new();
+        match demuxer.read_headers(&mut buf, &mut info) {
+            Err(Error::MoreDataNeeded(32)) => {}
+            r => panic!("unexpected result: {:?}", r),
+        }
+
+        let mut demuxer = Context::new(
+            IvfDemuxer::new(),
+            AccReader::with_capacity(4, Chunked(Cursor::new(IVF))),
+        );
+        demuxer.read_headers().unwrap();
+        assert_eq!(demuxer.demuxer().header.as_ref().unwrap().nframe, 25);
+
+        let mut packets = 0;
+        while let Event::NewPacket(_) = demuxer.read_event().unwrap() {
+            packets += 1;
+        }
+        assert_eq!(packets, 25);
+    }
+
+    #[test]
+    fn truncated_header() {
+        let data: &[u8] = b"DKIF\0\0\x20\0VP80";
+        let mut demuxer = Context::new(IvfDemuxer::new(), AccReader::new(Cursor::new(data)));
+        match demuxer.read_headers() {
+            Err(Error::Io(e)) => assert_eq!(e.kind(), std::io::ErrorKind::UnexpectedEof),
+            r => panic!("unexpected result: {:?}", r),
+        }
+    }
+
+    #[test]
+    fn render_size() {
+        let key = crate::test_util::vp9_keyframe(640, 360, Some((480, 360)));
+        let data = crate::test_util::ivf(b"VP90", &[(0, &key), (1, &[0x86, 0x00])]);
+        let mut demuxer = Context::new(IvfDemuxer::new(), AccReader::new(Cursor::new(data)));
+        demuxer.read_headers().unwrap();
+        assert_eq!(demuxer.demuxer().render_size(), None);
+
+        while let Event::NewPacket(_) = demuxer.read_event().unwrap() {
+            assert_eq!(demuxer.demuxer().render_size(), Some((480, 360)));
+        }
+    }
+
+    #[test]
+    fn short_header() {
+        let mut data = crate::test_util::header(b"VP80", 2);
+        data[6..8].copy_from_slice(&28u16.to_le_bytes());
+        data.truncate(28);
+        let frames = crate::test_util::ivf(b"VP80", &[(0, &[0x50, 1]), (1, &[0x51])]);
+        data.extend_from_slice(&frames[IVF_HEADER_LEN..]);
+
+        let mut demuxer = Context::new(IvfDemuxer::new(), AccReader::new(Cursor::new(&data)));
+        assert!(demuxer.read_headers().is_err());
+
+        let mut ivf_demuxer = IvfDemuxer::new();
+        ivf_demuxer.set_lenient(true);
+        let mut demuxer = Context::new(ivf_demuxer, AccReader::new(Cursor::new(&data)));
+        demuxer.read_headers().unwrap();
+        assert_eq!(demuxer.demuxer().raw_header_bytes().unwrap().len(), 28);
+
+        let mut packets = Vec::new();
+        while let Event::NewPacket(pkt) = demuxer.read_event().unwrap() {
+            packets.push((pkt.t.pts, pkt.data));
+        }
+        assert_eq!(packets, [(Some(0), vec![0x50, 1]), (Some(1), vec![0x51])]);
+    }
+
+    #[test]
+    fn long_header() {
+        let mut data = crate::test_util::header(b"VP80", 2);
+        data[6..8].copy_from_slice(&36u16.to_le_bytes());
+        data.extend_from_slice(&[0xff; 4]);
+        let frames = crate::test_util::ivf(b"VP80", &[(0, &[0x50, 1]), (1, &[0x51])]);
+        data.extend_from_slice(&frames[IVF_HEADER_LEN..]);
+
+        let reader = AccReader::with_capacity(16, Cursor::new(&data));
+        let mut demuxer = Context::new(IvfDemuxer::new(), reader);
+        demuxer.read_headers().unwrap();
+        assert_eq!(demuxer.demuxer().raw_header_bytes().unwrap().len(), 36);
+
+        let mut packets = Vec::new();
+        while let Event::NewPacket(pkt) = demuxer.read_event().unwrap() {
+            packets.push((pkt.t.pts, pkt.data));
+        }
+        assert_eq!(packets, [(Some(0), vec![0x50, 1]), (Some(1), vec![0x51])]);
+    }
+
+    #[test]
+    fn keyframe_policy() {
+        const KEY: &[u8] = &[0x50, 0x01, 0x00, 0x9d, 0x01, 0x2a, 0x40, 0x01, 0xf0, 0x00];
+        const INTER: &[u8] = &[0x51, 0x01, 0x00, 0x00];
+        let data = crate::test_util::ivf(b"VP80", &[(0, KEY), (1, INTER), (2, KEY)]);
+
+        let keyframes = |policy| {
+            let mut ivf_demuxer = IvfDemuxer::new();
+            ivf_demuxer.set_keyframe_policy(policy);
+            let mut demuxer = Context::new(ivf_demuxer, AccReader::new(Cursor::new(&data)));
+            demuxer.read_headers().unwrap();
+
+            let mut keys = Vec::new();
+            while let Event::NewPacket(pkt) = demuxer.read_event().unwrap() {
+                keys.push(pkt.is_key);
+            }
+            keys
+        };
+
+        assert_eq!(keyframes(KeyframePolicy::AllFalse), [false, false, false]);
+        assert_eq!(keyframes(KeyframePolicy::FirstOnly), [true, false, false]);
+        assert_eq!(keyframes(KeyframePolicy::Detect), [true, false, true]);
+    }
+
+    #[test]
+    fn demux_all() {
+        let (header, packets) = IvfDemuxer::demux_all(IVF).unwrap();
+        assert_eq!(header.codec, Codec::AV1);
+        assert_eq!(packets.len(), 25);
+        assert_eq!(packets[0].data.len(), 0x952);
+
+        let mut demuxer = Context::new(IvfDemuxer::new(), AccReader::new(Cursor::new(IVF)));
+        demuxer.read_headers().unwrap();
+        for expected in &packets {
+            match demuxer.read_event().unwrap() {
+                Event::NewPacket(pkt) => {
+                    assert_eq!(pkt.data, expected.data);
+                    assert_eq!(pkt.t.pts, expected.t.pts);
+                    assert_eq!(pkt.t.timebase, expected.t.timebase);
+                }
+                e => panic!("unexpected event: {:?}", e),
+            }
+        }
+    }
+
+    #[test]
+    fn big_endian_header() {
+        let mut data = Vec::new();
+        data.extend_from_slice(b"DKIF");
+        data.extend_from_slice(&0u16.to_be_bytes());
+        data.extend_from_slice(&32u16.to_be_bytes());
+        data.extend_from_slice(b"VP80");
+        data.extend_from_slice(&320u16.to_be_bytes());
+        data.extend_from_slice(&240u16.to_be_bytes());
+        data.extend_from_slice(&30u32.to_be_bytes());
+        data.extend_from_slice(&1u32.to_be_bytes());
+        data.extend_from_slice(&1u32.to_be_bytes());
+        data.extend_from_slice(&0u32.to_be_bytes());
+        let frames = crate::test_util::ivf(b"VP80", &[(0, &[0x50, 1])]);
+        data.extend_from_slice(&frames[IVF_HEADER_LEN..]);
+
+        let (_, header) = ivf_header(&data).unwrap();
+        assert!(header.looks_byte_swapped());
+
+        let mut ivf_demuxer = IvfDemuxer::new();
+        ivf_demuxer.set_assume_big_endian(true);
+        let mut demuxer = Context::new(ivf_demuxer, AccReader::new(Cursor::new(&data)));
+        demuxer.read_headers().unwrap();
+        let header = demuxer.demuxer().header.as_ref().unwrap();
+        assert!(!header.looks_byte_swapped());
+        assert_eq!((header.width, header.height), (320, 240));
+        assert_eq!((header.rate, header.scale, header.nframe), (30, 1, 1));
+
+        match demuxer.read_event().unwrap() {
+            Event::NewPacket(pkt) => assert_eq!(pkt.data, [0x50, 1]),
+            e => panic!("unexpected event: {:?}", e),
+        }
+    }
+
+    #[test]
+    fn narrow_timestamps() {
+        let high = 0xdead_beef_0000_0000u64;
+        let frames: &[(u64, &[u8])] = &[
+            (high | 0xffff_fffe, &[0x50]),
+            (high | 0xffff_ffff, &[0x51]),
+            (0, &[0x51]),
+            (1, &[0x51]),
+        ];
+        let data = crate::test_util::ivf(b"VP80", frames);
+
+        let mut ivf_demuxer = IvfDemuxer::new();
+        ivf_demuxer.set_narrow_timestamps(true);
+        ivf_demuxer.set_compute_durations(true);
+        let mut demuxer = Context::new(ivf_demuxer, AccReader::new(Cursor::new(&data)));
+        demuxer.read_headers().unwrap();
+
+        let mut packets = Vec::new();
+        loop {
+            match demuxer.read_event().unwrap() {
+                Event::NewPacket(pkt) => packets.push((pkt.t.pts.unwrap(), pkt.t.duration)),
+                Event::Continue => {}
+                Event::Eof => break,
+                e => panic!("unexpected event: {:?}", e),
+            }
+        }
+        let base = 0xffff_fffe;
+        assert_eq!(
+            packets,
+            [
+                (base, Some(1)),
+                (base + 1, Some(1)),
+                (base + 2, Some(1)),
+                (base + 3, None)
+            ]
+        );
+    }
+
+    #[test]
+    fn codec_config() {
+        let mut demuxer = Context::new(IvfDemuxer::new(), AccReader::new(Cursor::new(IVF)));
+        demuxer.read_headers().unwrap();
+        let config = demuxer.demuxer().codec_config().unwrap();
+        // the sequence header follows the temporal delimiter
+        assert_eq!(config, &IVF[32 + 12 + 2..32 + 12 + 2 + 13]);
+        assert_eq!(demuxer.info.streams[0].params.extradata, Some(config));
+
+        let data = crate::test_util::ivf(b"VP80", &[(0, &[0x50, 1])]);
+        let mut demuxer = Context::new(IvfDemuxer::new(), AccReader::new(Cursor::new(data)));
+        demuxer.read_headers().unwrap();
+        while let Event::NewPacket(_) = demuxer.read_event().unwrap() {}
+        assert_eq!(demuxer.demuxer().codec_config(), None);
+    }
+
+    #[test]
+    fn timestamp_clamping() {
+        let timestamps = [0, 2, 4, 6, 0xdead_beef, 10, 12, 1, 16];
+        let frames = timestamps
+            .iter()
+            .map(|&ts| (ts, &[0x51u8][..]))
+            .collect::<Vec<_>>();
+        let data = crate::test_util::ivf(b"VP80", &frames);
+
+        let demux = |clamping| {
+            let mut ivf_demuxer = IvfDemuxer::new();
+            ivf_demuxer.set_timestamp_clamping(clamping);
+            let mut demuxer = Context::new(ivf_demuxer, AccReader::new(Cursor::new(&data)));
+            demuxer.read_headers().unwrap();
+            let mut packets = Vec::new();
+            while let Event::NewPacket(pkt) = demuxer.read_event().unwrap() {
+                packets.push((pkt.t.pts.unwrap(), pkt.is_corrupted));
+            }
+            packets
+        };
+
+        let expected = timestamps.iter().map(|&ts| (ts as i64, false));
+        assert!(demux(None).into_iter().eq(expected));
+
+        let packets = demux(Some(4.0));
+        assert_eq!(
+            packets.iter().map(|p| p.0).collect::<Vec<_>>(),
+            [0, 2, 4, 6, 8, 10, 12, 14, 16]
+        );
+        assert_eq!(
+            packets.iter().map(|p| p.1).collect::<Vec<_>>(),
+            [false, false, false, false, true, false, false, true, false]
+        );
+    }
+
+    #[test]
+    fn enforce_frame_count() {
+        let frames: &[(u64, &[u8])] = &[(0, &[0x50]), (1, &[0x51]), (2, &[0x51])];
+        let mut data = crate::test_util::ivf(b"VP80", frames);
+        data[24..28].copy_from_slice(&2u32.to_le_bytes());
+
+        let demux = |enforce, data: &[u8]| {
+            let mut ivf_demuxer = IvfDemuxer::new();
+            ivf_demuxer.set_enforce_frame_count(enforce);
+            let mut demuxer = Context::new(ivf_demuxer, AccReader::new(Cursor::new(data)));
+            demuxer.read_headers().unwrap();
+            let mut packets = 0;
+            loop {
+                match demuxer.read_event() {
+                    Ok(Event::NewPacket(_)) => packets += 1,
+                    Ok(_) => return (packets, None),
+                    Err(Error::Io(e)) => return (packets, Some(e)),
+                    Err(e) => panic!("unexpected error: {:?}", e),
+                }
+            }
+        };
+
+        assert_eq!(demux(false, &data).0, 3);
+        let (packets, err) = demux(true, &data);
+        assert_eq!(packets, 2);
+        let err = err.unwrap();
+        let inner = err.get_ref().unwrap().downcast_ref::<IvfError>();
+        assert!(matches!(inner, Some(IvfError::TooManyFrames(2))));
+
+        data[24..28].copy_from_slice(&0u32.to_le_bytes());
+        assert_eq!(demux(true, &data).0, 3);
+    }
+
+    #[test]
+    fn hexdump() {
+        let header = ivf_header(IVF).unwrap().1;
+        let dump = header.hexdump();
+        let lines = dump.lines().collect::<Vec<_>>();
+
+        assert_eq!(lines.len(), 10);
+        assert_eq!(lines[0], r#"0x00  44 4b 49 46  magic     "DKIF""#);
+        assert_eq!(lines[3], r#"0x08  41 56 30 31  fourcc    "AV01""#);
+        assert_eq!(lines[6], "0x10  19 00 00 00  rate      25");
+        assert_eq!(lines[8], "0x18  19 00 00 00  count     25");
+        for (line, (label, offset, _)) in lines.iter().zip(HEADER_FIELDS) {
+            assert!(line.starts_with(&format!("{:#04x}", offset)));
+            assert!(line.contains(label));
+        }
+
+        let short = header_hexdump(&IVF[..28]);
+        assert!(short.ends_with("0x1c               reserved  missing\n"));
+    }
+
+    #[test]
+    fn probe_with_hint() {
+        let scores = [None, Some("mkv"), Some("vp9"), Some("ivf"), Some(".AV1")]
+            .iter()
+            .map(|&ext| super::probe_with_hint(IVF, ext))
+            .collect::<Vec<_>>();
+        assert_eq!(scores, [32, 32, 48, 48, 64]);
+
+        assert_eq!(super::probe_with_hint(&IVF[8..], Some("av1")), 0);
+    }
+
+    #[test]
+    fn probe_window() {
+        assert_eq!(IVF_DESC.probe(&IVF[..32]), 32);
+        assert_eq!(IVF_DESC.probe(&IVF[..33]), 32);
+        assert_eq!(IVF_DESC.probe(IVF), 32);
+        assert_eq!(IVF_DESC.probe(&IVF[..20]), 0);
+        assert_eq!(IVF_DESC.probe(&IVF[..2]), 0);
+    }
+
+    #[test]
+    fn handle_events() {
+        let mut packets = 0;
+
+        let flow = handle_event(Event::NewPacket(Packet::new()), |_| packets += 1);
+        assert_eq!(flow, ControlFlow::Continue(()));
+        assert_eq!(
+            handle_event(Event::Continue, |_| packets += 1),
+            ControlFlow::Continue(())
+        );
+        assert_eq!(
+            handle_event(Event::MoreDataNeeded(4), |_| packets += 1),
+            ControlFlow::Continue(())
+        );
+        assert_eq!(
+            handle_event(Event::Eof, |_| packets += 1),
+            ControlFlow::Break(())
+        );
+
+        assert_eq!(packets, 1);
+    }
 }
@@ -0,0 +1,232 @@
+//!
+//! In-place fixes for existing IVF files.
+//!
+//! These helpers only rewrite the bytes they are about, no remuxing is
+//! involved.
+//!
+
+use std::io::{self, Read, Seek, SeekFrom, Write};
+
+use log::debug;
+
+use av_bitstream::byteread::*;
+use av_bitstream::bytewrite::*;
+use av_data::rational::Rational64;
+use av_format::error::*;
+
+use crate::reader::read_header_only;
+use crate::scan::frame_ranges;
+
+/// Checks that `f` starts with the IVF signature.
+fn check_magic<R: Read + Seek>(f: &mut R) -> Result<()> {
+    let mut magic = [0u8; 4];
+    f.seek(SeekFrom::Start(0))?;
+    f.read_exact(&mut magic)?;
+    if &magic != b"DKIF" {
+        debug!("invalid signature: {:?}", magic);
+        return Err(Error::InvalidData);
+    }
+    Ok(())
+}
+
+/// Rewrites the codec FourCC stored in the header, leaving everything else
+/// untouched.
+pub fn patch_fourcc<RWS: Read + Write + Seek>(mut f: RWS, new: [u8; 4]) -> Result<()> {
+    check_magic(&mut f)?;
+    f.seek(SeekFrom::Start(8))?;
+    f.write_all(&new)?;
+    f.flush()?;
+    Ok(())
+}
+
+/// Rewrites the frame timestamps so they are strictly increasing, leaving
+/// the payloads untouched.
+///
+/// Every time the timestamps go back or stall, e.g. on a reset or a wrap
+/// around, the median frame duration is used as the step instead. Only
+/// the timestamp fields that change are written.
+pub fn normalize_timestamps<RWS: Read + Write + Seek>(mut f: RWS) -> Result<()> {
+    let ranges = frame_ranges(&mut f)?;
+
+    let mut timestamps = Vec::with_capacity(ranges.len());
+    for &(start, _) in &ranges {
+        let mut ts = [0u8; 8];
+        f.seek(SeekFrom::Start(start + 4))?;
+        f.read_exact(&mut ts)?;
+        timestamps.push(get_u64l(&ts));
+    }
+
+    let mut deltas = timestamps
+        .windows(2)
+        .filter_map(|pair| pair[1].checked_sub(pair[0]))
+        .filter(|&delta| delta > 0)
+        .collect::<Vec<_>>();
+    deltas.sort_unstable();
+    let step = deltas.get(deltas.len() / 2).copied().unwrap_or(1);
+
+    let mut prev: Option<(u64, u64)> = None;
+    for (&(start, _), &ts) in ranges.iter().zip(&timestamps) {
+        let fixed = match prev {
+            Some((prev_ts, prev_fixed)) => match ts.checked_sub(prev_ts) {
+                Some(delta) if delta > 0 => prev_fixed.saturating_add(delta),
+                _ => prev_fixed.saturating_add(step),
+            },
+            None => ts,
+        };
+        if fixed != ts {
+            debug!("timestamp at {}: {} -> {}", start, ts, fixed);
+            let mut buf = [0u8; 8];
+            put_u64l(&mut buf, fixed);
+            f.seek(SeekFrom::Start(start + 4))?;
+            f.write_all(&buf)?;
+        }
+        prev = Some((ts, fixed));
+    }
+    f.flush()?;
+    Ok(())
+}
+
+/// Switches the header to the `new_scale / new_rate` timebase, rescaling
+/// every frame timestamp to the nearest unit of it. The payloads are left
+/// untouched.
+///
+/// Fails with `InvalidInput` if the new timebase is degenerate or a
+/// timestamp does not fit in it, before writing anything.
+pub fn change_timebase<RWS: Read + Write + Seek>(
+    mut f: RWS,
+    new_rate: u32,
+    new_scale: u32,
+) -> Result<()> {
+    f.seek(SeekFrom::Start(0))?;
+    let header = read_header_only(&mut f)?;
+    f.seek(SeekFrom::Start(0))?;
+    let ranges = frame_ranges(&mut f)?;
+    let out_of_range = || {
+        Error::Io(io::Error::new(
+            io::ErrorKind::InvalidInput,
+            "timestamp out of range",
+        ))
+    };
+    if new_rate == 0 || new_scale == 0 {
+        return Err(out_of_range());
+    }
+    let timebase = Rational64::new_raw(new_scale as i64, new_rate as i64);
+
+    let mut timestamps = Vec::with_capacity(ranges.len());
+    for &(start, _) in &ranges {
+        let mut ts = [0u8; 8];
+        f.seek(SeekFrom::Start(start + 4))?;
+        f.read_exact(&mut ts)?;
+        let rescaled = header
+            .timestamp(get_u64l(&ts))
+            .rescale(timebase)
+            .ok_or_else(out_of_range)?;
+        timestamps.push(rescaled.value);
+    }
+
+    let mut buf = [0u8; 8];
+    put_u32l(&mut buf[0..4], new_rate);
+    put_u32l(&mut buf[4..8], new_scale);
+    f.seek(SeekFrom::Start(16))?;
+    f.write_all(&buf)?;
+    for (&(start, _), &ts) in ranges.iter().zip(&timestamps) {
+        put_u64l(&mut buf, ts);
+        f.seek(SeekFrom::Start(start + 4))?;
+        f.write_all(&buf)?;
+    }
+    f.flush()?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::Cursor;
+
+    use super::*;
+    use crate::common::Codec;
+    use crate::reader::IvfFrameReader;
+    use crate::test_util::ivf;
+
+    #[test]
+    fn patch_aom1() {
+        let mut data = ivf(b"AOM1", &[(0, &[0x12, 0x00]), (1, &[0x32, 0x00])]);
+        assert!(IvfFrameReader::new(Cursor::new(&data)).is_err());
+
+        patch_fourcc(Cursor::new(&mut data), *b"AV01").unwrap();
+
+        let reader = IvfFrameReader::new(Cursor::new(&data)).unwrap();
+        assert_eq!(reader.header().codec, Codec::AV1);
+        assert_eq!(reader.count(), 2);
+    }
+
+    #[test]
+    fn normalize_reset() {
+        let frames: Vec<(u64, &[u8])> = [0, 2, 4, 6, 0, 2, 4]
+            .iter()
+            .enumerate()
+            .map(|(i, &ts)| {
+                (
+                    ts,
+                    if i % 2 == 0 {
+                        &[1u8, 2][..]
+                    } else {
+                        &[3u8][..]
+                    },
+                )
+            })
+            .collect();
+        let mut data = ivf(b"VP80", &frames);
+        let original = IvfFrameReader::new(Cursor::new(&data))
+            .unwrap()
+            .collect_frames()
+            .unwrap();
+
+        normalize_timestamps(Cursor::new(&mut data)).unwrap();
+
+        let fixed = IvfFrameReader::new(Cursor::new(&data))
+            .unwrap()
+            .collect_frames()
+            .unwrap();
+        let timestamps = fixed.iter().map(|f| f.timestamp).collect::<Vec<_>>();
+        assert_eq!(timestamps, [0, 2, 4, 6, 8, 10, 12]);
+        for (a, b) in original.iter().zip(&fixed) {
+            assert_eq!(a.data, b.data);
+        }
+    }
+
+    #[test]
+    fn change_timebase() {
+        let mut data = ivf(b"VP80", &[(0, &[0x50]), (1, &[0x51]), (3, &[0x51])]);
+        let original = data.clone();
+
+        // 30 fps to 1/90000 units
+        super::change_timebase(Cursor::new(&mut data), 90000, 1).unwrap();
+
+        let reader = IvfFrameReader::new(Cursor::new(&data)).unwrap();
+        let header = reader.header().clone();
+        assert_eq!((header.rate, header.scale), (90000, 1));
+        let frames = reader.collect_frames().unwrap();
+        let timestamps = frames.iter().map(|f| f.timestamp).collect::<Vec<_>>();
+        assert_eq!(timestamps, [0, 3000, 9000]);
+        assert_eq!(frames[2].data, [0x51]);
+
+        let err = super::change_timebase(Cursor::new(&mut data), 0, 1).unwrap_err();
+        assert!(matches!(err, Error::Io(e) if e.kind() == io::ErrorKind::InvalidInput));
+        let mut data = original;
+        super::change_timebase(Cursor::new(&mut data), 15, 1).unwrap();
+        let frames = IvfFrameReader::new(Cursor::new(&data))
+            .unwrap()
+            .collect_frames()
+            .unwrap();
+        let timestamps = frames.iter().map(|f| f.timestamp).collect::<Vec<_>>();
+        assert_eq!(timestamps, [0, 1, 2]);
+    }
+
+    #[test]
+    fn patch_invalid() {
+        let mut data = vec![0u8; 32];
+
+        assert!(patch_fourcc(Cursor::new(&mut data), *b"AV01").is_err());
+        assert_eq!(data, vec![0u8; 32]);
+    }
+}
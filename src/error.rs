@@ -0,0 +1,138 @@
+//!
+//! Errors specific to the IVF format.
+//!
+
+use std::error;
+use std::fmt;
+use std::io;
+
+/// Errors reported while reading or writing IVF streams.
+#[derive(Debug)]
+#[non_exhaustive]
+pub enum IvfError {
+    /// The stream does not start with a valid IVF header.
+    InvalidHeader,
+    /// The stream ends in the middle of the header.
+    TruncatedHeader,
+    /// The stream ends in the middle of a frame header or payload.
+    TruncatedFrame,
+    /// The stream holds more frames than the configured limit.
+    TooManyFrames(u32),
+    /// A frame or the trailer was written before the header.
+    HeaderNotWritten,
+    /// A packet of another stream was fed to the muxer.
+    WrongStream {
+        /// Stream index of the packet
+        got: isize,
+        /// Stream index of the IVF stream
+        expected: isize,
+    },
+    /// The payloads do not match the checksum stored in the header, see
+    /// `IvfDemuxer::set_verify_checksum`.
+    ChecksumMismatch {
+        /// Checksum stored in the header
+        expected: u32,
+        /// Checksum of the payloads read
+        actual: u32,
+    },
+    /// The header only makes sense once the CR bytes preceding LF bytes
+    /// are dropped: the file was most likely transferred in text mode.
+    LikelyTextModeCorruption,
+    /// The underlying reader or writer failed.
+    Io(io::Error),
+}
+
+impl fmt::Display for IvfError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            IvfError::InvalidHeader => f.write_str("missing or invalid IVF header"),
+            IvfError::TruncatedHeader => f.write_str("truncated IVF header"),
+            IvfError::TruncatedFrame => f.write_str("truncated IVF frame"),
+            IvfError::TooManyFrames(limit) => write!(f, "more than {} frames", limit),
+            IvfError::HeaderNotWritten => f.write_str("IVF header not written yet"),
+            IvfError::ChecksumMismatch { expected, actual } => write!(
+                f,
+                "payload checksum {:#010x}, expected {:#010x}",
+                actual, expected
+            ),
+            IvfError::WrongStream { got, expected } => {
+                write!(f, "packet of stream {}, expected stream {}", got, expected)
+            }
+            IvfError::LikelyTextModeCorruption => f.write_str(
+                "corrupted IVF header, CR bytes were inserted before LF bytes, \
+                 transfer the file again in binary mode",
+            ),
+            IvfError::Io(e) => write!(f, "I/O error: {}", e),
+        }
+    }
+}
+
+impl error::Error for IvfError {
+    fn source(&self) -> Option<&(dyn error::Error + 'static)> {
+        match self {
+            IvfError::Io(e) => Some(e),
+            _ => None,
+        }
+    }
+}
+
+impl From<io::Error> for IvfError {
+    fn from(e: io::Error) -> Self {
+        IvfError::Io(e)
+    }
+}
+
+impl From<IvfError> for io::Error {
+    fn from(e: IvfError) -> Self {
+        let kind = match e {
+            IvfError::InvalidHeader
+            | IvfError::TooManyFrames(_)
+            | IvfError::ChecksumMismatch { .. }
+            | IvfError::LikelyTextModeCorruption => io::ErrorKind::InvalidData,
+            IvfError::TruncatedHeader | IvfError::TruncatedFrame => io::ErrorKind::UnexpectedEof,
+            IvfError::HeaderNotWritten | IvfError::WrongStream { .. } => {
+                io::ErrorKind::InvalidInput
+            }
+            IvfError::Io(e) => return e,
+        };
+        io::Error::new(kind, e)
+    }
+}
+
+/// Maps an early end of stream reported by `read_exact` to `truncated`.
+pub(crate) fn map_eof(e: io::Error, truncated: IvfError) -> io::Error {
+    if e.kind() == io::ErrorKind::UnexpectedEof {
+        truncated.into()
+    } else {
+        e
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::Cursor;
+
+    use super::*;
+    use crate::reader::IvfFrameReader;
+
+    #[test]
+    fn truncated_frame_is_unexpected_eof() {
+        let mut data = crate::test_util::ivf(b"VP80", &[(0, &[1, 2, 3, 4])]);
+        data.truncate(data.len() - 2);
+
+        let mut reader = IvfFrameReader::new(Cursor::new(data)).unwrap();
+        let err = reader.next().unwrap().unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::UnexpectedEof);
+        let inner = err.get_ref().unwrap().downcast_ref::<IvfError>();
+        assert!(matches!(inner, Some(IvfError::TruncatedFrame)));
+    }
+
+    #[test]
+    fn invalid_header_is_invalid_data() {
+        let err = io::Error::from(IvfError::InvalidHeader);
+        assert_eq!(err.kind(), io::ErrorKind::InvalidData);
+
+        let err = io::Error::from(IvfError::Io(io::ErrorKind::BrokenPipe.into()));
+        assert_eq!(err.kind(), io::ErrorKind::BrokenPipe);
+    }
+}
@@ -0,0 +1,310 @@
+//!
+//! Optional frame index trailer.
+//!
+//! This is an extension specific to this crate, other tools will not read
+//! it. The trailer is written after the last frame and is laid out as
+//!
+//! - the `IVFX` magic
+//! - the number of entries as u32
+//! - for each frame, its timestamp and the absolute offset of its frame
+//!   header as u64, the most significant bit of the offset flagging
+//!   keyframes
+//! - the total trailer length in bytes as u32
+//! - the `IVFX` magic again, so the trailer can be found from the end
+//!
+//! All values are little-endian.
+//!
+//! `IvfDemuxer` looks for the trailer when asked to, through `set_use_index`
+//! or `set_seek_timestamp`, to flag the keyframes and to start at the one
+//! requested. Readers stop at a trailer only once it validates in full, so
+//! a frame that merely starts with the magic is still read as a frame.
+//!
+
+use std::io::{self, Read, Seek, SeekFrom, Write};
+
+use av_bitstream::byteread::*;
+use av_bitstream::bytewrite::*;
+
+/// Magic marking the index trailer.
+pub const INDEX_MAGIC: &[u8; 4] = b"IVFX";
+
+/// Index entry locating a single frame.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct IndexEntry {
+    /// Frame timestamp
+    pub timestamp: u64,
+    /// Absolute offset of the frame header
+    pub offset: u64,
+    /// Whether the frame is a keyframe
+    pub is_key: bool,
+}
+
+const ENTRY_LEN: usize = 16;
+const KEYFRAME_FLAG: u64 = 1 << 63;
+const FOOTER_LEN: usize = 8;
+
+/// Returns the length in bytes of the index trailer for `entries` frames.
+pub(crate) fn index_len(entries: usize) -> u64 {
+    8 + entries as u64 * ENTRY_LEN as u64 + FOOTER_LEN as u64
+}
+
+/// Returns the length the index trailer starting `data` would have, if
+/// `data` starts with the magic and holds the entry count.
+pub(crate) fn trailer_len(data: &[u8]) -> Option<u64> {
+    if data.len() < 8 || !data.starts_with(INDEX_MAGIC) {
+        return None;
+    }
+    Some(index_len(get_u32l(&data[4..8]) as usize))
+}
+
+/// Checks that `data` is exactly one well-formed index trailer.
+pub(crate) fn is_trailer(data: &[u8]) -> bool {
+    match trailer_len(data) {
+        Some(len) if len == data.len() as u64 => {
+            let footer = &data[data.len() - FOOTER_LEN..];
+            get_u32l(&footer[0..4]) as u64 == len && &footer[4..] == INDEX_MAGIC
+        }
+        _ => false,
+    }
+}
+
+/// Writes the index trailer for `entries`.
+pub fn write_index<W: Write>(w: &mut W, entries: &[IndexEntry]) -> io::Result<()> {
+    let len = index_len(entries.len()) as usize;
+    let mut buf = vec![0u8; len];
+
+    buf[0..4].copy_from_slice(INDEX_MAGIC);
+    put_u32l(&mut buf[4..8], entries.len() as u32);
+    for (entry, chunk) in entries
+        .iter()
+        .zip(buf[8..len - FOOTER_LEN].chunks_exact_mut(ENTRY_LEN))
+    {
+        put_u64l(&mut chunk[0..8], entry.timestamp);
+        let flag = if entry.is_key { KEYFRAME_FLAG } else { 0 };
+        put_u64l(&mut chunk[8..16], entry.offset | flag);
+    }
+    put_u32l(&mut buf[len - 8..len - 4], len as u32);
+    buf[len - 4..].copy_from_slice(INDEX_MAGIC);
+
+    w.write_all(&buf)
+}
+
+/// Reads the index trailer at the end of `r`, if present.
+///
+/// The stream position is left unspecified.
+pub fn read_index<R: Read + Seek>(r: &mut R) -> io::Result<Option<Vec<IndexEntry>>> {
+    let end = r.seek(SeekFrom::End(0))?;
+    if end < (8 + FOOTER_LEN) as u64 {
+        return Ok(None);
+    }
+
+    let mut footer = [0u8; FOOTER_LEN];
+    r.seek(SeekFrom::End(-(FOOTER_LEN as i64)))?;
+    r.read_exact(&mut footer)?;
+    if &footer[4..] != INDEX_MAGIC {
+        return Ok(None);
+    }
+
+    let len = get_u32l(&footer[0..4]) as u64;
+    if len > end || len < (8 + FOOTER_LEN) as u64 {
+        return Ok(None);
+    }
+
+    let mut buf = vec![0u8; len as usize];
+    r.seek(SeekFrom::Start(end - len))?;
+    r.read_exact(&mut buf)?;
+    let count = get_u32l(&buf[4..8]) as usize;
+    if &buf[0..4] != INDEX_MAGIC || 8 + count * ENTRY_LEN + FOOTER_LEN != buf.len() {
+        return Ok(None);
+    }
+
+    let entries = buf[8..buf.len() - FOOTER_LEN]
+        .chunks_exact(ENTRY_LEN)
+        .map(|chunk| {
+            let offset = get_u64l(&chunk[8..16]);
+            IndexEntry {
+                timestamp: get_u64l(&chunk[0..8]),
+                offset: offset & !KEYFRAME_FLAG,
+                is_key: offset & KEYFRAME_FLAG != 0,
+            }
+        })
+        .collect();
+
+    Ok(Some(entries))
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::Cursor;
+    use std::sync::Arc;
+
+    use av_data::packet::Packet;
+    use av_format::buffer::AccReader;
+    use av_format::demuxer::{Context as DemuxerContext, Event};
+    use av_format::muxer::{Context as MuxerContext, Writer};
+
+    use super::*;
+    use crate::demuxer::IvfDemuxer;
+    use crate::muxer::IvfMuxer;
+    use crate::reader::IvfFrameReader;
+
+    fn mux_indexed(sizes: &[usize]) -> Vec<u8> {
+        let mut ivf_muxer = IvfMuxer::new();
+        ivf_muxer.set_write_index(true);
+        let mut muxer = MuxerContext::new(ivf_muxer, Writer::new(Cursor::new(Vec::new())));
+        muxer.configure().unwrap();
+        muxer.write_header().unwrap();
+        for (i, &size) in sizes.iter().enumerate() {
+            let pkt = Packet {
+                data: vec![i as u8; size],
+                pos: Some(i * 10),
+                is_key: i == 0,
+                ..Default::default()
+            };
+            muxer.write_packet(Arc::new(pkt)).unwrap();
+        }
+        muxer.write_trailer().unwrap();
+
+        muxer.writer().as_ref().0.get_ref().clone()
+    }
+
+    #[test]
+    fn index_round_trip() {
+        let data = mux_indexed(&[5, 0, 7]);
+
+        let index = read_index(&mut Cursor::new(&data)).unwrap().unwrap();
+        assert_eq!(
+            index,
+            [
+                IndexEntry {
+                    timestamp: 0,
+                    offset: 32,
+                    is_key: true,
+                },
+                IndexEntry {
+                    timestamp: 10,
+                    offset: 49,
+                    is_key: false,
+                },
+                IndexEntry {
+                    timestamp: 20,
+                    offset: 61,
+                    is_key: false,
+                },
+            ]
+        );
+
+        let mut reader = IvfFrameReader::new(Cursor::new(&data)).unwrap();
+        assert!(reader.seek_to_timestamp(15).unwrap());
+        let frame = reader.next().unwrap().unwrap();
+        assert_eq!(frame.timestamp, 10);
+        assert_eq!(reader.count(), 1);
+
+        let mut ivf_demuxer = IvfDemuxer::new();
+        ivf_demuxer.set_index(index);
+        let mut demuxer = DemuxerContext::new(ivf_demuxer, AccReader::new(Cursor::new(&data)));
+        demuxer.read_headers().unwrap();
+        let mut keyframes = Vec::new();
+        while let Event::NewPacket(pkt) = demuxer.read_event().unwrap() {
+            keyframes.push(pkt.is_key);
+        }
+        assert_eq!(keyframes, [true, false, false]);
+
+        // found by the demuxer itself
+        let mut ivf_demuxer = IvfDemuxer::new();
+        ivf_demuxer.set_use_index(true);
+        let mut demuxer = DemuxerContext::new(ivf_demuxer, AccReader::new(Cursor::new(&data)));
+        demuxer.read_headers().unwrap();
+        let mut keyframes = Vec::new();
+        while let Event::NewPacket(pkt) = demuxer.read_event().unwrap() {
+            keyframes.push(pkt.is_key);
+        }
+        assert_eq!(keyframes, [true, false, false]);
+
+        // not looked for, but still not mistaken for a frame
+        let reader = AccReader::with_capacity(16, Cursor::new(&data));
+        let mut demuxer = DemuxerContext::new(IvfDemuxer::new(), reader);
+        demuxer.read_headers().unwrap();
+        let mut sizes = Vec::new();
+        while let Event::NewPacket(pkt) = demuxer.read_event().unwrap() {
+            sizes.push(pkt.data.len());
+        }
+        assert_eq!(sizes, [5, 0, 7]);
+    }
+
+    #[test]
+    fn demuxer_seek() {
+        let mut builder = crate::writer::IvfBuilder::new(crate::common::Codec::VP8, 64, 48);
+        builder.set_write_index(true);
+        for ts in 0..10u64 {
+            builder.frame(ts, ts % 4 == 0, &[ts as u8; 3]);
+        }
+        let data = builder.build();
+
+        let demux = |timestamp| {
+            let mut ivf_demuxer = IvfDemuxer::new();
+            ivf_demuxer.set_seek_timestamp(timestamp);
+            let reader = AccReader::with_capacity(16, Cursor::new(&data));
+            let mut demuxer = DemuxerContext::new(ivf_demuxer, reader);
+            demuxer.read_headers().unwrap();
+            let mut packets = Vec::new();
+            while let Event::NewPacket(pkt) = demuxer.read_event().unwrap() {
+                packets.push((pkt.data[0], pkt.is_key));
+            }
+            packets
+        };
+
+        assert_eq!(
+            demux(7),
+            [
+                (4, true),
+                (5, false),
+                (6, false),
+                (7, false),
+                (8, true),
+                (9, false)
+            ]
+        );
+        assert_eq!(demux(8)[0], (8, true));
+        assert_eq!(demux(100).len(), 2);
+
+        let mut unindexed = data.clone();
+        unindexed.truncate(unindexed.len() - 1);
+        let mut ivf_demuxer = IvfDemuxer::new();
+        ivf_demuxer.set_seek_timestamp(7);
+        let reader = AccReader::new(Cursor::new(&unindexed));
+        let mut demuxer = DemuxerContext::new(ivf_demuxer, reader);
+        demuxer.read_headers().unwrap();
+        match demuxer.read_event().unwrap() {
+            Event::NewPacket(pkt) => assert_eq!(pkt.data[0], 0),
+            e => panic!("unexpected event: {:?}", e),
+        }
+    }
+
+    #[test]
+    fn embedded_seek() {
+        let mut builder = crate::writer::IvfBuilder::new(crate::common::Codec::VP8, 64, 48);
+        builder.set_write_index(true);
+        for ts in 0..4u64 {
+            builder.frame(ts, ts % 2 == 0, &[ts as u8; 3]);
+        }
+        let mut data = b"leading".to_vec();
+        data.extend_from_slice(&builder.build());
+        data.extend_from_slice(b"trailing");
+
+        let mut reader = IvfFrameReader::new_at_offset(Cursor::new(&data), 7).unwrap();
+        assert!(!reader.seek_to_timestamp(2).unwrap());
+        assert_eq!(reader.next().unwrap().unwrap().data, [0; 3]);
+    }
+
+    #[test]
+    fn no_index() {
+        let data = crate::test_util::ivf(b"VP80", &[(0, &[1, 2, 3])]);
+
+        assert_eq!(read_index(&mut Cursor::new(&data)).unwrap(), None);
+
+        let mut reader = IvfFrameReader::new(Cursor::new(&data)).unwrap();
+        assert!(!reader.seek_to_timestamp(0).unwrap());
+        assert_eq!(reader.count(), 1);
+    }
+}
@@ -13,6 +13,25 @@
 //! This projects relies on [rust-av](https://github.com/rust-av/rust-av) toolkit
 //!
 
+#[cfg(feature = "futures")]
+pub mod async_reader;
+mod bitstream;
+mod checksum;
 pub mod common;
 pub mod demuxer;
+pub mod edit;
+pub mod error;
+pub mod index;
 pub mod muxer;
+pub mod pool;
+pub mod reader;
+pub mod remux;
+pub mod scan;
+pub mod sidecar;
+pub mod validate;
+pub mod writer;
+
+#[cfg(test)]
+mod test_util;
+
+pub use common::{IVF_FRAME_HEADER_LEN, IVF_HEADER_LEN};
@@ -2,12 +2,19 @@
 //! Implement the muxer trait from av-format and expose all the correct
 //! abstraction to handle them. Refer to the `Muxer` trait for more info.
 //!
+//! The `Muxer` implementation never seeks, so any `Write` works as output,
+//! including pipes and stdout. The frame count in the header is the
+//! expected `duration`, taken from the stream info or set as an option,
+//! and is left as is by `write_trailer`. Use `finalize_header` to patch
+//! it on seekable outputs.
 //!
 
-use std::io::Write;
+use std::fmt;
+use std::io::{Seek, SeekFrom, Write};
+use std::sync::mpsc::Receiver;
 use std::sync::Arc;
 
-use log::{debug, trace};
+use log::{debug, error, trace, warn};
 
 use av_bitstream::bytewrite::*;
 use av_data::packet::Packet;
@@ -19,7 +26,29 @@ use av_format::error::*;
 pub use av_format::muxer::Muxer;
 pub use av_format::muxer::{Context, Writer};
 
-use crate::common::Codec;
+use crate::checksum::Crc32;
+use crate::common::{
+    Codec, FrameCountWidth, IVF_FRAME_HEADER_LEN, IVF_HEADER_LEN, IVF_RESERVED_OFFSET,
+    TIMESTAMP_KEYFRAME_FLAG,
+};
+use crate::demuxer::IvfHeader;
+use crate::error::IvfError;
+use crate::index::{index_len, write_index, IndexEntry};
+
+/// Payload rewriting hook, see `IvfMuxer::set_packet_transform`.
+///
+/// It gets each payload before it is written and may rewrite it in place,
+/// changing its length included.
+pub type TransformFn = Box<dyn FnMut(&mut Vec<u8>) + Send>;
+
+/// Payload rewriting hook set with `IvfMuxer::set_packet_transform`
+struct PacketTransform(TransformFn);
+
+impl fmt::Debug for PacketTransform {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str("PacketTransform")
+    }
+}
 
 #[derive(Debug)]
 pub struct IvfMuxer {
@@ -29,8 +58,83 @@ pub struct IvfMuxer {
     frame_rate: Rational32,
     scale: u32,
     codec: Codec,
-    duration: u32,
+    duration: u64,
+    reserved: u32,
     info: Option<GlobalInfo>,
+    keyframe_in_timestamp_msb: bool,
+    frame_rate_override: Option<Rational32>,
+    index: Option<Vec<IndexEntry>>,
+    write_index: bool,
+    reorder_depth: usize,
+    reorder_buffer: Vec<Arc<Packet>>,
+    transform: Option<PacketTransform>,
+    strict: bool,
+    write_buffer: WriteBuffer,
+    header_written: bool,
+    frame_count_width: FrameCountWidth,
+    stream_index: isize,
+    checksum: Option<Crc32>,
+    /// Bytes of the frame records written since the header
+    frame_bytes: u64,
+}
+
+/// Coalesces small writes, see `IvfMuxer::set_write_buffer`
+#[derive(Debug, Default)]
+struct WriteBuffer {
+    data: Vec<u8>,
+    capacity: usize,
+}
+
+impl WriteBuffer {
+    fn write<W: Write>(&mut self, buf: &mut Writer<W>, bytes: &[u8]) -> std::io::Result<()> {
+        if self.data.len() + bytes.len() > self.capacity {
+            self.flush(buf)?;
+        }
+        if bytes.len() >= self.capacity {
+            buf.write_all(bytes)
+        } else {
+            self.data.extend_from_slice(bytes);
+            Ok(())
+        }
+    }
+
+    fn flush<W: Write>(&mut self, buf: &mut Writer<W>) -> std::io::Result<()> {
+        if !self.data.is_empty() {
+            buf.write_all(&self.data)?;
+            self.data.clear();
+        }
+        Ok(())
+    }
+}
+
+/// A sink that discards everything written to it, counting the bytes.
+///
+/// Used by `IvfMuxer::validate_only`, it can be given to any `Writer`.
+#[derive(Clone, Debug, Default)]
+pub struct NullWriter {
+    written: u64,
+}
+
+impl NullWriter {
+    pub fn new() -> NullWriter {
+        NullWriter::default()
+    }
+
+    /// Returns the number of bytes written so far.
+    pub fn bytes_written(&self) -> u64 {
+        self.written
+    }
+}
+
+impl Write for NullWriter {
+    fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+        self.written += buf.len() as u64;
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> std::io::Result<()> {
+        Ok(())
+    }
 }
 
 impl Default for IvfMuxer {
@@ -43,7 +147,22 @@ impl Default for IvfMuxer {
             scale: Default::default(),
             codec: Default::default(),
             duration: Default::default(),
+            reserved: 0,
             info: Default::default(),
+            keyframe_in_timestamp_msb: false,
+            frame_rate_override: None,
+            index: None,
+            write_index: false,
+            reorder_depth: 0,
+            reorder_buffer: Vec::new(),
+            transform: None,
+            strict: false,
+            write_buffer: WriteBuffer::default(),
+            header_written: false,
+            frame_count_width: FrameCountWidth::default(),
+            stream_index: 0,
+            checksum: None,
+            frame_bytes: 0,
         }
     }
 }
@@ -52,6 +171,359 @@ impl IvfMuxer {
     pub fn new() -> IvfMuxer {
         IvfMuxer::default()
     }
+
+    /// Creates a muxer writing `header` verbatim, frame count included.
+    ///
+    /// Unlike going through `GlobalInfo`, every header field is kept, so
+    /// a straight remux reproduces the original header byte for byte. The
+    /// header length is the exception: 32 bytes are written, and declared,
+    /// even for a shorter or longer source header.
+    /// Calling `configure` without global info keeps these values.
+    pub fn from_header(header: &IvfHeader) -> IvfMuxer {
+        IvfMuxer {
+            version: header.version,
+            width: header.width,
+            height: header.height,
+            frame_rate: Rational32::new_raw(header.rate as i32, header.scale as i32),
+            scale: header.scale,
+            codec: header.codec,
+            // the upper half is only written with FrameCountWidth::Bits64
+            duration: header.frame_count(FrameCountWidth::Bits64),
+            reserved: header.reserved,
+            ..IvfMuxer::default()
+        }
+    }
+
+    /// Override the frame rate stored in the header.
+    ///
+    /// Only the header rate and scale fields are affected: the frame
+    /// timestamps and the frame count are written as usual, and the value
+    /// takes precedence over whatever `configure` derives from the global info.
+    pub fn set_frame_rate(&mut self, frame_rate: Rational32) {
+        self.frame_rate_override = Some(frame_rate);
+    }
+
+    /// Choose how the frame count is written, `FrameCountWidth::Bits32` by
+    /// default.
+    ///
+    /// With `FrameCountWidth::Bits64` the reserved field holds the upper
+    /// half of the stream duration.
+    pub fn set_frame_count_width(&mut self, width: FrameCountWidth) {
+        self.frame_count_width = width;
+    }
+
+    /// Compute a checksum of the payloads, to be stored in the reserved
+    /// header field by `write_checksum`.
+    ///
+    /// This is an extension specific to this crate: the field holds the
+    /// CRC-32 of all the payloads, which other tools ignore. It is checked
+    /// by `IvfDemuxer::set_verify_checksum`.
+    ///
+    /// The field must be free: `write_header` rejects the checksum along
+    /// with `FrameCountWidth::Bits64` or a non-zero reserved field kept
+    /// from the source header.
+    pub fn set_write_checksum(&mut self, enable: bool) {
+        self.checksum = enable.then(Crc32::default);
+    }
+
+    /// Buffer up to `depth` packets and write them in timestamp order.
+    ///
+    /// Whenever the buffer is full the packet with the lowest timestamp is
+    /// written, the remaining ones are flushed by `write_trailer`. A depth of
+    /// 0, the default, writes packets as they come.
+    pub fn set_reorder_depth(&mut self, depth: usize) {
+        self.reorder_depth = depth;
+    }
+
+    /// Write an index trailer after the last frame.
+    ///
+    /// The trailer is an extension of this crate and other tools will not
+    /// read it, see the `index` module for its layout.
+    pub fn set_write_index(&mut self, enable: bool) {
+        self.write_index = enable;
+        self.set_record_index(enable);
+    }
+
+    /// Record the timestamp and output offset of every frame written, see
+    /// `output_index`, without writing an index trailer.
+    pub fn set_record_index(&mut self, enable: bool) {
+        self.index = if enable { Some(Vec::new()) } else { None };
+    }
+
+    /// Returns the frames written so far, as recorded when
+    /// `set_record_index` or `set_write_index` is enabled.
+    ///
+    /// The offsets are those of the frame headers from the start of the
+    /// output, ready to serve as a seek table for it.
+    pub fn output_index(&self) -> Option<&[IndexEntry]> {
+        self.index.as_deref()
+    }
+
+    /// Mark keyframes by setting the most significant bit of the frame
+    /// timestamp.
+    ///
+    /// This is not part of the IVF specification, but some tools rely on it.
+    /// Use `IvfDemuxer::set_decode_keyframe_in_timestamp_msb` to read the
+    /// flag back.
+    pub fn set_encode_keyframe_in_timestamp_msb(&mut self, enable: bool) {
+        self.keyframe_in_timestamp_msb = enable;
+    }
+
+    /// Rewrite each payload with `transform` before writing it.
+    ///
+    /// The frame size is taken from the rewritten payload.
+    pub fn set_packet_transform(&mut self, transform: TransformFn) {
+        self.transform = Some(PacketTransform(transform));
+    }
+
+    /// Reject headers and frames that players would not handle, instead
+    /// of writing them as they are.
+    ///
+    /// The header must declare non-zero dimensions and frame rate, and
+    /// payloads must fit in the 32-bit frame size. Packets belonging to
+    /// another stream fail with `IvfError::WrongStream` instead of being
+    /// dropped.
+    pub fn set_strict(&mut self, strict: bool) {
+        self.strict = strict;
+    }
+
+    /// Gather frames in an internal buffer of `capacity` bytes and hand
+    /// them to the writer in large chunks, instead of issuing two writes
+    /// per frame.
+    ///
+    /// The buffer is flushed by `write_trailer` and `finalize_header`. A
+    /// capacity of 0, the default, disables it, which is what callers
+    /// writing to an already buffered sink want.
+    pub fn set_write_buffer(&mut self, capacity: usize) {
+        self.write_buffer = WriteBuffer {
+            data: Vec::with_capacity(capacity),
+            capacity,
+        };
+    }
+
+    /// Runs the whole muxing process on `packets`, discarding the output,
+    /// and returns the number of bytes that would have been written.
+    ///
+    /// The muxer is left in its finished state, use a fresh one to write
+    /// the actual output.
+    pub fn validate_only<I>(&mut self, packets: I) -> Result<u64>
+    where
+        I: IntoIterator<Item = Arc<Packet>>,
+    {
+        let mut buf = Writer::new(NullWriter::new());
+        self.write_header(&mut buf)?;
+        for pkt in packets {
+            self.write_packet(&mut buf, pkt)?;
+        }
+        self.write_trailer(&mut buf)?;
+        self.header_written = false;
+        Ok(buf.as_ref().0.bytes_written())
+    }
+
+    /// Returns the size in bytes of the output once `write_trailer` is
+    /// done, counting the frames written so far, the ones still held by
+    /// `set_reorder_depth` and the index trailer, see `predict_size`.
+    ///
+    /// The held payloads are counted before `set_packet_transform`
+    /// rewrites them.
+    pub fn predicted_size(&self) -> u64 {
+        let held = self
+            .reorder_buffer
+            .iter()
+            .map(|pkt| (IVF_FRAME_HEADER_LEN + pkt.data.len()) as u64)
+            .sum::<u64>();
+        let index = match (&self.index, self.write_index) {
+            (Some(index), true) => index_len(index.len() + self.reorder_buffer.len()),
+            _ => 0,
+        };
+        IVF_HEADER_LEN as u64 + self.frame_bytes + held + index
+    }
+
+    /// Fails with `IvfError::HeaderNotWritten` unless a header was written
+    fn check_header_written(&self) -> Result<()> {
+        if !self.header_written {
+            error!("No header written yet");
+            return Err(std::io::Error::from(IvfError::HeaderNotWritten).into());
+        }
+        Ok(())
+    }
+
+    /// Header matching the current configuration
+    fn header(&self) -> IvfHeader {
+        let frame_rate = self.frame_rate_override.unwrap_or(self.frame_rate);
+        let reserved = match self.frame_count_width {
+            FrameCountWidth::Bits32 => self.reserved,
+            FrameCountWidth::Bits64 => (self.duration >> 32) as u32,
+        };
+
+        IvfHeader {
+            version: self.version,
+            // the 32 bytes written, whatever the source header declared
+            header_len: IVF_HEADER_LEN as u16,
+            width: self.width,
+            height: self.height,
+            rate: *frame_rate.numer() as u32,
+            scale: *frame_rate.denom() as u32,
+            codec: self.codec,
+            nframe: self.duration as u32,
+            reserved,
+        }
+    }
+
+    /// Writes a header declaring no frames, to be rewritten later by
+    /// `finalize_header`.
+    ///
+    /// This allows writing frames before the codec or the dimensions are
+    /// known.
+    pub fn write_placeholder_header<W: Write>(&mut self, buf: &mut Writer<W>) -> Result<()> {
+        let header = IvfHeader {
+            nframe: 0,
+            ..self.header()
+        };
+        buf.write_all(&header.to_bytes())?;
+        self.header_written = true;
+        self.frame_bytes = 0;
+        Ok(())
+    }
+
+    /// Rewrites the header at the start of `buf` with `header` and
+    /// `frame_count`, then moves back to where the writer was.
+    pub fn finalize_header<W: Write + Seek>(
+        &mut self,
+        buf: &mut Writer<W>,
+        frame_count: u32,
+        header: &IvfHeader,
+    ) -> Result<()> {
+        let header = IvfHeader {
+            header_len: IVF_HEADER_LEN as u16,
+            nframe: frame_count,
+            ..header.clone()
+        };
+
+        self.write_buffer.flush(buf)?;
+        let pos = buf.stream_position()?;
+        buf.seek(SeekFrom::Start(0))?;
+        buf.write_all(&header.to_bytes())?;
+        buf.seek(SeekFrom::Start(pos))?;
+
+        Ok(())
+    }
+
+    /// Muxes the `(timestamp, payload)` frames received from `rx` into
+    /// `w` until every sender is dropped, e.g. to write frames produced by
+    /// an encoder thread.
+    ///
+    /// `header` is written first, its frame count is patched once the
+    /// channel is closed. Returns the number of frames written.
+    pub fn run_from_channel<W: Write + Seek>(
+        w: W,
+        rx: Receiver<(u64, Vec<u8>)>,
+        header: &IvfHeader,
+    ) -> Result<u32> {
+        let mut muxer = IvfMuxer::from_header(header);
+        let mut buf = Writer::new(w);
+        muxer.write_header(&mut buf)?;
+
+        let mut frame_count = 0u32;
+        for (timestamp, data) in rx {
+            let mut pkt = Packet::new();
+            pkt.data = data;
+            pkt.t.pts = Some(timestamp as i64);
+            muxer.write_packet(&mut buf, Arc::new(pkt))?;
+            frame_count += 1;
+        }
+        muxer.write_trailer(&mut buf)?;
+        muxer.finalize_header(&mut buf, frame_count, header)?;
+
+        Ok(frame_count)
+    }
+
+    /// Stores the checksum of the payloads written so far in the reserved
+    /// header field, then moves back to where the writer was.
+    ///
+    /// Call it after `write_trailer`, the `Muxer` trait cannot seek back
+    /// by itself. Requires `set_write_checksum`.
+    pub fn write_checksum<W: Write + Seek>(&mut self, buf: &mut Writer<W>) -> Result<()> {
+        let checksum = match self.checksum {
+            Some(checksum) => checksum.value(),
+            None => return Err(Error::InvalidData),
+        };
+        let mut bytes = [0u8; 4];
+        put_u32l(&mut bytes, checksum);
+
+        self.write_buffer.flush(buf)?;
+        let pos = buf.stream_position()?;
+        buf.seek(SeekFrom::Start(IVF_RESERVED_OFFSET as u64))?;
+        buf.write_all(&bytes)?;
+        buf.seek(SeekFrom::Start(pos))?;
+
+        Ok(())
+    }
+
+    fn write_frame<W: Write>(&mut self, buf: &mut Writer<W>, pkt: &Packet) -> Result<()> {
+        let mut frame_header = [0; IVF_FRAME_HEADER_LEN];
+        let mut timestamp = packet_timestamp(pkt);
+        if let Some(index) = self.index.as_mut() {
+            index.push(IndexEntry {
+                timestamp,
+                offset: (buf.position() + self.write_buffer.data.len()) as u64,
+                is_key: pkt.is_key,
+            });
+        }
+        if self.keyframe_in_timestamp_msb && pkt.is_key {
+            timestamp |= TIMESTAMP_KEYFRAME_FLAG;
+        }
+
+        let mut transformed;
+        let data = match self.transform.as_mut() {
+            Some(PacketTransform(transform)) => {
+                transformed = pkt.data.clone();
+                transform(&mut transformed);
+                &transformed
+            }
+            None => &pkt.data,
+        };
+
+        if self.strict && data.len() > u32::MAX as usize {
+            error!("Frame too large: {} bytes", data.len());
+            return Err(Error::InvalidData);
+        }
+
+        put_u32l(&mut frame_header[0..4], data.len() as u32);
+        put_u64l(&mut frame_header[4..12], timestamp);
+
+        self.write_buffer.write(buf, &frame_header)?;
+        self.write_buffer.write(buf, data)?;
+        self.frame_bytes += (IVF_FRAME_HEADER_LEN + data.len()) as u64;
+        if let Some(checksum) = self.checksum.as_mut() {
+            checksum.update(data);
+        }
+
+        Ok(())
+    }
+
+    /// Removes the packet with the lowest timestamp from the reorder buffer
+    fn pop_earliest(&mut self) -> Arc<Packet> {
+        let (earliest, _) = self
+            .reorder_buffer
+            .iter()
+            .enumerate()
+            .min_by_key(|(_, pkt)| packet_timestamp(pkt))
+            .unwrap();
+        self.reorder_buffer.remove(earliest)
+    }
+}
+
+/// Timestamp stored in the frame header for `pkt`
+///
+/// Older versions of this crate stored the timestamp in `pos`, keep
+/// honoring it for packets without a `pts`.
+fn packet_timestamp(pkt: &Packet) -> u64 {
+    pkt.t
+        .pts
+        .map(|pts| pts as u64)
+        .or(pkt.pos.map(|pos| pos as u64))
+        .unwrap_or_default()
 }
 
 /// This should be called if IvfMuxer::info is set
@@ -59,16 +531,26 @@ impl Muxer for IvfMuxer {
     fn configure(&mut self) -> Result<()> {
         match self.info.as_ref() {
             Some(info) if !info.streams.is_empty() => {
-                self.duration = info.streams[0].duration.unwrap_or_default() as u32;
+                self.duration = info.streams[0].duration.unwrap_or_default();
+                self.stream_index = info.streams[0].index as isize;
                 let params = &info.streams[0].params;
                 self.version = 0;
+                self.reserved = 0;
+                if let Some(header) = info.streams[0]
+                    .user_private
+                    .as_ref()
+                    .and_then(|private| private.downcast_ref::<IvfHeader>())
+                {
+                    self.version = header.version;
+                    self.reserved = header.reserved;
+                }
                 if let Some(MediaKind::Video(video)) = &params.kind {
                     self.width = video.width as u16;
                     self.height = video.height as u16;
                 };
                 self.frame_rate = info
                     .timebase
-                    .map(|tb| Rational32::new(*tb.denom() as i32, *tb.numer() as i32))
+                    .map(|tb| Rational32::new_raw(*tb.denom() as i32, *tb.numer() as i32))
                     .unwrap_or_else(|| Rational32::new(30, 1));
                 self.scale = 1;
                 self.codec = match params.codec_id.as_deref() {
@@ -93,44 +575,70 @@ impl Muxer for IvfMuxer {
     fn write_header<W: Write>(&mut self, buf: &mut Writer<W>) -> Result<()> {
         debug!("Write muxer header: {:?}", self);
 
-        let codec = match self.codec {
-            Codec::VP8 => b"VP80",
-            Codec::VP9 => b"VP90",
-            Codec::AV1 => b"AV01",
-        };
-
-        let mut tmp_buf = [0u8; 20];
-        buf.write_all(b"DKIF")?;
-        put_u16l(&mut tmp_buf[0..2], self.version);
-        put_u16l(&mut tmp_buf[2..4], 32);
-        buf.write_all(&tmp_buf[..4])?;
-        buf.write_all(codec)?;
-        put_u16l(&mut tmp_buf[0..2], self.width);
-        put_u16l(&mut tmp_buf[2..4], self.height);
-        put_u32l(&mut tmp_buf[4..8], *self.frame_rate.numer() as u32);
-        put_u32l(&mut tmp_buf[8..12], *self.frame_rate.denom() as u32);
-        put_u32l(&mut tmp_buf[12..16], self.duration);
-        put_u32l(&mut tmp_buf[16..20], 0);
-        buf.write_all(&tmp_buf)?;
+        let header = self.header();
+        if self.strict
+            && (header.width == 0 || header.height == 0 || header.rate == 0 || header.scale == 0)
+        {
+            error!("Invalid header: {:?}", header);
+            return Err(Error::InvalidData);
+        }
+        if self.checksum.is_some()
+            && (self.frame_count_width == FrameCountWidth::Bits64 || header.reserved != 0)
+        {
+            error!(
+                "The checksum would overwrite the reserved field: {:?}",
+                header
+            );
+            return Err(Error::InvalidData);
+        }
+        buf.write_all(&header.to_bytes())?;
+        self.header_written = true;
+        self.frame_bytes = 0;
 
         Ok(())
     }
 
     fn write_packet<W: Write>(&mut self, buf: &mut Writer<W>, pkt: Arc<Packet>) -> Result<()> {
-        trace!("Write packet: {:?}", pkt.pos);
+        trace!("Write packet: {}", packet_timestamp(&pkt));
+        self.check_header_written()?;
 
-        let mut frame_header = [0; 12];
+        // packets not tied to any stream are ours
+        if pkt.stream_index >= 0 && pkt.stream_index != self.stream_index {
+            let (got, expected) = (pkt.stream_index, self.stream_index);
+            if self.strict {
+                error!("Packet of stream {}, expected {}", got, expected);
+                let e = IvfError::WrongStream { got, expected };
+                return Err(std::io::Error::from(e).into());
+            }
+            warn!("Dropping packet of stream {}, expected {}", got, expected);
+            return Ok(());
+        }
 
-        put_u32l(&mut frame_header[0..4], pkt.data.len() as u32);
-        put_u64l(&mut frame_header[4..12], pkt.pos.unwrap_or_default() as u64);
+        if self.reorder_depth == 0 {
+            return self.write_frame(buf, &pkt);
+        }
 
-        buf.write_all(&frame_header)?;
-        buf.write_all(&pkt.data)?;
+        self.reorder_buffer.push(pkt);
+        if self.reorder_buffer.len() > self.reorder_depth {
+            let pkt = self.pop_earliest();
+            self.write_frame(buf, &pkt)?;
+        }
 
         Ok(())
     }
 
     fn write_trailer<W: Write>(&mut self, buf: &mut Writer<W>) -> Result<()> {
+        self.check_header_written()?;
+        while !self.reorder_buffer.is_empty() {
+            let pkt = self.pop_earliest();
+            self.write_frame(buf, &pkt)?;
+        }
+        self.write_buffer.flush(buf)?;
+
+        if let (Some(index), true) = (self.index.as_ref(), self.write_index) {
+            debug!("Write index with {} entries", index.len());
+            write_index(buf, index)?;
+        }
         buf.flush()?;
         Ok(())
     }
@@ -155,7 +663,7 @@ impl Muxer for IvfMuxer {
                 self.scale = get_val_int(val)? as u32;
             }
             "duration" => {
-                self.duration = get_val_int(val)? as u32;
+                self.duration = get_val_int(val)? as u64;
             }
             _ => {
                 return Err(av_format::error::Error::InvalidData);
@@ -165,6 +673,14 @@ impl Muxer for IvfMuxer {
     }
 }
 
+/// Computes the size in bytes of an IVF file with a header of `header_len`
+/// bytes followed by frames of the given payload sizes.
+pub fn predict_size(header_len: u16, frame_sizes: &[u32]) -> u64 {
+    frame_sizes.iter().fold(header_len as u64, |acc, &size| {
+        acc + IVF_FRAME_HEADER_LEN as u64 + size as u64
+    })
+}
+
 fn get_val_rational(val: Value<'_>) -> Result<Rational32> {
     match val {
         Value::I64(val) => Ok(Rational32::new(val as i32, 1)),
@@ -212,4 +728,533 @@ mod tests {
             .write_all(muxer.writer().as_ref().0.get_ref())
             .unwrap();
     }
+
+    #[test]
+    fn frame_rate_override() {
+        use av_data::params::{CodecParams, VideoInfo};
+        use av_data::rational::Rational64;
+        use av_format::stream::Stream;
+
+        let mut info = GlobalInfo {
+            duration: None,
+            timebase: Some(Rational64::new(1, 25)),
+            streams: Vec::new(),
+        };
+        info.add_stream(Stream::from_params(
+            &CodecParams {
+                kind: Some(MediaKind::Video(VideoInfo {
+                    width: 64,
+                    height: 48,
+                    format: None,
+                })),
+                codec_id: Some("av1".into()),
+                extradata: None,
+                bit_rate: 0,
+                convergence_window: 0,
+                delay: 0,
+            },
+            Rational64::new(1, 25),
+        ));
+
+        let mut ivf_muxer = IvfMuxer::new();
+        ivf_muxer.set_frame_rate(Rational32::new(60000, 1001));
+        let mut muxer = Context::new(ivf_muxer, Writer::new(Cursor::new(Vec::new())));
+        muxer.set_global_info(info).unwrap();
+        muxer.configure().unwrap();
+        muxer.write_header().unwrap();
+        let pkt = Packet {
+            data: vec![1, 2, 3],
+            pos: Some(7),
+            ..Default::default()
+        };
+        muxer.write_packet(Arc::new(pkt)).unwrap();
+        muxer.write_trailer().unwrap();
+
+        let out = muxer.writer().as_ref().0.get_ref();
+        assert_eq!(&out[16..20], &60000u32.to_le_bytes());
+        assert_eq!(&out[20..24], &1001u32.to_le_bytes());
+        assert_eq!(&out[36..44], &7u64.to_le_bytes());
+        assert_eq!(&out[44..], &[1, 2, 3]);
+    }
+
+    #[test]
+    fn reorder() {
+        use crate::reader::IvfFrameReader;
+
+        let mut ivf_muxer = IvfMuxer::new();
+        ivf_muxer.set_reorder_depth(3);
+        let mut muxer = Context::new(ivf_muxer, Writer::new(Cursor::new(Vec::new())));
+        muxer.configure().unwrap();
+        muxer.write_header().unwrap();
+        for pts in [0, 3, 1, 2, 6, 4, 5] {
+            let mut pkt = Packet::zeroed(1);
+            pkt.t.pts = Some(pts);
+            muxer.write_packet(Arc::new(pkt)).unwrap();
+        }
+        muxer.write_trailer().unwrap();
+
+        let data = muxer.writer().as_ref().0.get_ref();
+        let timestamps = IvfFrameReader::new(Cursor::new(data))
+            .unwrap()
+            .map(|frame| frame.unwrap().timestamp)
+            .collect::<Vec<_>>();
+
+        assert_eq!(timestamps, [0, 1, 2, 3, 4, 5, 6]);
+    }
+
+    #[test]
+    fn checksum() {
+        use crate::demuxer::IvfDemuxer;
+        use crate::error::IvfError;
+        use av_format::buffer::AccReader;
+        use av_format::demuxer::{Context as DemuxerContext, Event};
+
+        let mut muxer = IvfMuxer::new();
+        muxer.set_write_checksum(true);
+        muxer.set_option("duration", Value::U64(3)).unwrap();
+        let mut writer = Writer::new(Cursor::new(Vec::new()));
+        muxer.write_header(&mut writer).unwrap();
+        for i in 0..3u8 {
+            let mut pkt = Packet::new();
+            pkt.data = vec![i; 10];
+            muxer.write_packet(&mut writer, Arc::new(pkt)).unwrap();
+        }
+        muxer.write_trailer(&mut writer).unwrap();
+        muxer.write_checksum(&mut writer).unwrap();
+        let mut data = writer.as_ref().0.get_ref().clone();
+        assert_ne!(data[28..32], [0; 4]);
+
+        let demux = |data: &[u8]| {
+            let mut ivf_demuxer = IvfDemuxer::new();
+            ivf_demuxer.set_verify_checksum(true);
+            let mut demuxer = DemuxerContext::new(ivf_demuxer, AccReader::new(Cursor::new(data)));
+            demuxer.read_headers().unwrap();
+            loop {
+                match demuxer.read_event() {
+                    Ok(Event::Eof) => return Ok(()),
+                    Ok(_) => {}
+                    Err(e) => return Err(e),
+                }
+            }
+        };
+
+        demux(&data).unwrap();
+        data[32 + 12 + 5] ^= 1;
+        match demux(&data) {
+            Err(Error::Io(e)) => {
+                let inner = e.get_ref().unwrap().downcast_ref::<IvfError>();
+                assert!(matches!(inner, Some(IvfError::ChecksumMismatch { .. })));
+            }
+            res => panic!("unexpected result: {:?}", res),
+        }
+
+        // the check does not depend on the declared frame count
+        let checksum = data[28..32].to_vec();
+        for nframe in [0u32, 2, 100] {
+            data[24..28].copy_from_slice(&nframe.to_le_bytes());
+            assert!(demux(&data).is_err());
+        }
+        data[32 + 12 + 5] ^= 1;
+        demux(&data).unwrap();
+
+        // nor on how the frames are split
+        let mut ivf_demuxer = IvfDemuxer::new();
+        ivf_demuxer.set_verify_checksum(true);
+        ivf_demuxer.set_partial_frames(true);
+        let reader = AccReader::with_capacity(40, Cursor::new(&data));
+        let mut demuxer = DemuxerContext::new(ivf_demuxer, reader);
+        demuxer.read_headers().unwrap();
+        while !matches!(demuxer.read_event().unwrap(), Event::Eof) {}
+
+        // standard files are not checked
+        data[28..32].copy_from_slice(&[0; 4]);
+        data[32 + 12 + 5] ^= 1;
+        demux(&data).unwrap();
+        data[28..32].copy_from_slice(&checksum);
+
+        // the field is not free to hold the checksum
+        let mut muxer = IvfMuxer::new();
+        muxer.set_write_checksum(true);
+        muxer.set_frame_count_width(FrameCountWidth::Bits64);
+        let mut writer = Writer::new(Cursor::new(Vec::new()));
+        assert!(muxer.write_header(&mut writer).is_err());
+
+        let header = IvfHeader {
+            reserved: 1,
+            ..crate::demuxer::ivf_header(&data).unwrap().1
+        };
+        let mut muxer = IvfMuxer::from_header(&header);
+        muxer.set_write_checksum(true);
+        assert!(muxer.write_header(&mut writer).is_err());
+    }
+
+    #[test]
+    fn two_phase_header() {
+        use crate::reader::IvfFrameReader;
+
+        let mut muxer = IvfMuxer::new();
+        let mut writer = Writer::new(Cursor::new(Vec::new()));
+        muxer.write_placeholder_header(&mut writer).unwrap();
+        for _ in 0..3 {
+            let pkt = Packet::zeroed(5);
+            muxer.write_packet(&mut writer, Arc::new(pkt)).unwrap();
+        }
+
+        let header = IvfHeader {
+            version: 0,
+            header_len: 32,
+            width: 320,
+            height: 240,
+            rate: 25,
+            scale: 1,
+            codec: Codec::AV1,
+            nframe: 0,
+            reserved: 0,
+        };
+        muxer.finalize_header(&mut writer, 3, &header).unwrap();
+        muxer.write_trailer(&mut writer).unwrap();
+
+        let data = writer.as_ref().0.get_ref();
+        assert_eq!(data.len(), 32 + 3 * (12 + 5));
+        let reader = IvfFrameReader::new(Cursor::new(data)).unwrap();
+        assert_eq!(reader.header().codec, Codec::AV1);
+        assert_eq!(reader.header().width, 320);
+        assert_eq!(reader.header().nframe, 3);
+        assert_eq!(reader.count(), 3);
+    }
+
+    #[test]
+    fn run_from_channel() {
+        use crate::reader::IvfFrameReader;
+        use std::sync::mpsc;
+
+        let (tx, rx) = mpsc::channel();
+        let encoder = std::thread::spawn(move || {
+            for i in 0..4u8 {
+                tx.send((i as u64, vec![i; i as usize + 1])).unwrap();
+            }
+        });
+
+        let header = crate::demuxer::ivf_header(&crate::test_util::header(b"VP80", 0))
+            .unwrap()
+            .1;
+        let mut out = Cursor::new(Vec::new());
+        let count = IvfMuxer::run_from_channel(&mut out, rx, &header).unwrap();
+        encoder.join().unwrap();
+        assert_eq!(count, 4);
+
+        let reader = IvfFrameReader::new(Cursor::new(out.into_inner())).unwrap();
+        assert_eq!(reader.header().nframe, 4);
+        let frames = reader.collect_frames().unwrap();
+        for (i, frame) in frames.iter().enumerate() {
+            assert_eq!(frame.timestamp, i as u64);
+            assert_eq!(frame.data, vec![i as u8; i + 1]);
+        }
+    }
+
+    #[test]
+    fn packet_transform() {
+        use crate::reader::IvfFrameReader;
+
+        let mut muxer = IvfMuxer::new();
+        muxer.set_packet_transform(Box::new(|data| {
+            data.splice(1..1, [0xaa, 0xbb]).for_each(drop)
+        }));
+        let mut writer = Writer::new(Cursor::new(Vec::new()));
+        muxer.write_header(&mut writer).unwrap();
+        let mut pkt = Packet::with_capacity(3);
+        pkt.data.extend_from_slice(&[1, 2, 3]);
+        muxer.write_packet(&mut writer, Arc::new(pkt)).unwrap();
+        muxer.write_trailer(&mut writer).unwrap();
+
+        let data = writer.as_ref().0.get_ref();
+        let frames = IvfFrameReader::new(Cursor::new(data))
+            .unwrap()
+            .collect_frames()
+            .unwrap();
+        assert_eq!(frames[0].size, 5);
+        assert_eq!(frames[0].data, [1, 0xaa, 0xbb, 2, 3]);
+    }
+
+    #[test]
+    fn header_not_written() {
+        let mut muxer = IvfMuxer::new();
+        let mut writer = Writer::new(Cursor::new(Vec::new()));
+        let pkt = Arc::new(Packet::zeroed(5));
+
+        for res in [
+            muxer.write_packet(&mut writer, pkt.clone()),
+            muxer.write_trailer(&mut writer),
+        ] {
+            match res {
+                Err(Error::Io(e)) => {
+                    let inner = e.get_ref().unwrap().downcast_ref::<IvfError>();
+                    assert!(matches!(inner, Some(IvfError::HeaderNotWritten)));
+                }
+                res => panic!("unexpected result: {:?}", res),
+            }
+        }
+        assert_eq!(writer.position(), 0);
+
+        muxer.write_header(&mut writer).unwrap();
+        muxer.write_packet(&mut writer, pkt).unwrap();
+        muxer.write_trailer(&mut writer).unwrap();
+    }
+
+    #[test]
+    fn wrong_stream() {
+        let mux = |strict| {
+            let mut muxer = IvfMuxer::new();
+            muxer.set_strict(strict);
+            muxer.set_option("width", Value::U64(64)).unwrap();
+            muxer.set_option("height", Value::U64(48)).unwrap();
+            let mut writer = Writer::new(Cursor::new(Vec::new()));
+            muxer.write_header(&mut writer).unwrap();
+
+            let mut pkt = Packet::zeroed(5);
+            pkt.stream_index = 0;
+            muxer.write_packet(&mut writer, Arc::new(pkt)).unwrap();
+            let mut pkt = Packet::zeroed(7);
+            pkt.stream_index = 1;
+            let res = muxer.write_packet(&mut writer, Arc::new(pkt));
+            (res, writer.position())
+        };
+
+        let (res, position) = mux(false);
+        assert!(res.is_ok());
+        assert_eq!(position, 32 + 12 + 5);
+
+        match mux(true).0 {
+            Err(Error::Io(e)) => {
+                let inner = e.get_ref().unwrap().downcast_ref::<IvfError>();
+                assert!(matches!(
+                    inner,
+                    Some(IvfError::WrongStream {
+                        got: 1,
+                        expected: 0
+                    })
+                ));
+            }
+            res => panic!("unexpected result: {:?}", res),
+        }
+    }
+
+    #[test]
+    fn validate_only() {
+        let packets = || (0..3).map(|_| Arc::new(Packet::zeroed(5)));
+
+        let mut muxer = IvfMuxer::new();
+        muxer.set_strict(true);
+        assert!(muxer.validate_only(packets()).is_err());
+
+        let mut muxer = IvfMuxer::new();
+        muxer.set_strict(true);
+        muxer.set_option("width", Value::U64(64)).unwrap();
+        muxer.set_option("height", Value::U64(48)).unwrap();
+        assert_eq!(
+            muxer.validate_only(packets()).unwrap(),
+            predict_size(32, &[5; 3])
+        );
+    }
+
+    #[test]
+    fn write_buffer() {
+        let mux = |capacity| {
+            let mut muxer = IvfMuxer::new();
+            muxer.set_write_index(true);
+            muxer.set_write_buffer(capacity);
+            let mut writer = Writer::new(Cursor::new(Vec::new()));
+            muxer.write_header(&mut writer).unwrap();
+            for size in [3, 40, 0, 7, 100] {
+                let mut pkt = Packet::zeroed(size);
+                pkt.t.pts = Some(size as i64);
+                muxer.write_packet(&mut writer, Arc::new(pkt)).unwrap();
+            }
+            muxer.write_trailer(&mut writer).unwrap();
+            writer.as_ref().0.get_ref().clone()
+        };
+
+        let unbuffered = mux(0);
+        assert_eq!(mux(16), unbuffered);
+        assert_eq!(mux(64), unbuffered);
+        assert_eq!(mux(4096), unbuffered);
+    }
+
+    #[test]
+    fn output_index() {
+        let mut muxer = IvfMuxer::new();
+        muxer.set_record_index(true);
+        let mut writer = Writer::new(Cursor::new(Vec::new()));
+        muxer.write_header(&mut writer).unwrap();
+        for (pts, size) in [(10, 3), (11, 40), (13, 0), (12, 7)] {
+            let mut pkt = Packet::zeroed(size);
+            pkt.t.pts = Some(pts);
+            pkt.is_key = pts == 10;
+            muxer.write_packet(&mut writer, Arc::new(pkt)).unwrap();
+        }
+        muxer.write_trailer(&mut writer).unwrap();
+        let data = writer.as_ref().0.get_ref().clone();
+
+        let index = muxer.output_index().unwrap();
+        let ranges = crate::scan::frame_ranges(Cursor::new(&data)).unwrap();
+        let offsets = index.iter().map(|entry| entry.offset).collect::<Vec<_>>();
+        assert_eq!(offsets, ranges.iter().map(|r| r.0).collect::<Vec<_>>());
+        let timestamps = index
+            .iter()
+            .map(|entry| entry.timestamp)
+            .collect::<Vec<_>>();
+        assert_eq!(timestamps, [10, 11, 13, 12]);
+        assert!(index[0].is_key);
+        // no trailer
+        assert_eq!(ranges.last().unwrap().1, data.len() as u64);
+    }
+
+    #[test]
+    fn from_header() {
+        use crate::reader::IvfFrameReader;
+
+        const IVF: &[u8] = include_bytes!("../assets/single_stream_av1.ivf");
+        let reader = IvfFrameReader::new(Cursor::new(IVF)).unwrap();
+
+        let mut muxer = IvfMuxer::from_header(reader.header());
+        let mut writer = Writer::new(Cursor::new(Vec::new()));
+        muxer.configure().unwrap();
+        muxer.write_header(&mut writer).unwrap();
+        for frame in reader {
+            let frame = frame.unwrap();
+            let mut pkt = Packet::with_capacity(frame.data.len());
+            pkt.data = frame.data;
+            pkt.t.pts = Some(frame.timestamp as i64);
+            muxer.write_packet(&mut writer, Arc::new(pkt)).unwrap();
+        }
+        muxer.write_trailer(&mut writer).unwrap();
+
+        assert_eq!(writer.as_ref().0.get_ref(), IVF);
+
+        // the length declared matches the 32 bytes written
+        for header_len in [28, 64] {
+            let header = IvfHeader {
+                header_len,
+                ..IvfFrameReader::new(Cursor::new(IVF))
+                    .unwrap()
+                    .header()
+                    .clone()
+            };
+            let mut muxer = IvfMuxer::from_header(&header);
+            let mut writer = Writer::new(Cursor::new(Vec::new()));
+            muxer.write_header(&mut writer).unwrap();
+            muxer
+                .write_packet(&mut writer, Arc::new(Packet::zeroed(3)))
+                .unwrap();
+            muxer.write_trailer(&mut writer).unwrap();
+
+            let data = writer.as_ref().0.get_ref();
+            let reader = IvfFrameReader::new(Cursor::new(data)).unwrap();
+            assert_eq!(reader.header().header_len, 32);
+            assert_eq!(reader.collect_frames().unwrap()[0].data, [0; 3]);
+        }
+    }
+
+    #[test]
+    fn frame_count_64() {
+        use crate::demuxer::IvfDemuxer;
+        use av_format::buffer::AccReader;
+        use av_format::demuxer::Context as DemuxerContext;
+
+        let mut data = crate::test_util::ivf(b"VP80", &[(0, &[0x50, 1])]);
+        data[24..32].copy_from_slice(&(1u64 << 32 | 5).to_le_bytes());
+
+        let mut ivf_demuxer = IvfDemuxer::new();
+        ivf_demuxer.set_frame_count_width(FrameCountWidth::Bits64);
+        let mut demuxer = DemuxerContext::new(ivf_demuxer, AccReader::new(Cursor::new(&data)));
+        demuxer.read_headers().unwrap();
+        let info = demuxer.info.clone();
+        assert_eq!(info.streams[0].duration, Some(1 << 32 | 5));
+
+        let mut ivf_muxer = IvfMuxer::new();
+        ivf_muxer.set_frame_count_width(FrameCountWidth::Bits64);
+        let mut muxer = Context::new(ivf_muxer, Writer::new(Cursor::new(Vec::new())));
+        muxer.set_global_info(info).unwrap();
+        muxer.configure().unwrap();
+        muxer.write_header().unwrap();
+
+        assert_eq!(&muxer.writer().as_ref().0.get_ref()[..32], &data[..32]);
+    }
+
+    #[test]
+    fn predicted_size() {
+        let sizes = [0u32, 17, 1024, 3];
+
+        let mut muxer = Context::new(IvfMuxer::new(), Writer::new(Cursor::new(Vec::new())));
+        muxer.configure().unwrap();
+        muxer.write_header().unwrap();
+        for &size in &sizes {
+            let pkt = Packet::zeroed(size as usize);
+            muxer.write_packet(Arc::new(pkt)).unwrap();
+        }
+        muxer.write_trailer().unwrap();
+
+        let written = muxer.writer().as_ref().0.get_ref().len() as u64;
+        assert_eq!(predict_size(32, &sizes), written);
+    }
+
+    #[test]
+    fn predicted_size_with_held_frames() {
+        let mut muxer = IvfMuxer::new();
+        muxer.set_reorder_depth(2);
+        muxer.set_write_index(true);
+        muxer.set_write_buffer(64);
+        let mut writer = Writer::new(Cursor::new(Vec::new()));
+        muxer.write_header(&mut writer).unwrap();
+        for (i, size) in [5usize, 40, 0, 100].into_iter().enumerate() {
+            let mut pkt = Packet::zeroed(size);
+            pkt.t.pts = Some(3 - i as i64);
+            muxer.write_packet(&mut writer, Arc::new(pkt)).unwrap();
+        }
+        let predicted = muxer.predicted_size();
+        assert_eq!(
+            predicted,
+            predict_size(32, &[5, 40, 0, 100]) + 8 + 4 * 16 + 8
+        );
+
+        muxer.write_trailer(&mut writer).unwrap();
+        assert_eq!(writer.as_ref().0.get_ref().len() as u64, predicted);
+    }
+
+    #[test]
+    fn keyframe_in_timestamp_msb() {
+        use av_format::buffer::AccReader;
+        use av_format::demuxer::{Context as DemuxerContext, Event};
+
+        use crate::demuxer::IvfDemuxer;
+
+        let mut ivf_muxer = IvfMuxer::new();
+        ivf_muxer.set_encode_keyframe_in_timestamp_msb(true);
+        let mut muxer = Context::new(ivf_muxer, Writer::new(Cursor::new(Vec::new())));
+        muxer.configure().unwrap();
+        muxer.write_header().unwrap();
+        for (pos, is_key) in [(0, true), (1, false), (2, false), (3, true)] {
+            let pkt = Packet {
+                data: vec![0xaa; 4],
+                pos: Some(pos),
+                is_key,
+                ..Default::default()
+            };
+            muxer.write_packet(Arc::new(pkt)).unwrap();
+        }
+        muxer.write_trailer().unwrap();
+
+        let data = muxer.writer().as_ref().0.get_ref().clone();
+        let mut demuxer_opts = IvfDemuxer::new();
+        demuxer_opts.set_decode_keyframe_in_timestamp_msb(true);
+        let mut demuxer = DemuxerContext::new(demuxer_opts, AccReader::new(Cursor::new(data)));
+        demuxer.read_headers().unwrap();
+
+        let mut frames = Vec::new();
+        while let Event::NewPacket(pkt) = demuxer.read_event().unwrap() {
+            frames.push((pkt.pos.unwrap(), pkt.is_key));
+        }
+
+        assert_eq!(frames, [(0, true), (1, false), (2, false), (3, true)]);
+    }
 }
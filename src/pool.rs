@@ -0,0 +1,83 @@
+//!
+//! Reuse of packet buffers across frames.
+//!
+//! The demuxer copies every payload into a freshly allocated `Vec`. With a
+//! `BufferPool` set through `IvfDemuxer::set_buffer_pool`, it copies them
+//! into buffers handed back by the consumer instead, once it is done with
+//! the packets. The pool is shared, since `Context` does not give access
+//! to the demuxer it owns.
+//!
+
+use std::sync::{Arc, Mutex};
+
+/// Shared stock of buffers to copy the frame payloads into.
+///
+/// Clones refer to the same pool.
+#[derive(Clone, Debug)]
+pub struct BufferPool {
+    buffers: Arc<Mutex<Vec<Vec<u8>>>>,
+    max_buffers: usize,
+}
+
+impl BufferPool {
+    /// Creates an empty pool keeping at most `max_buffers` buffers, the
+    /// extra ones being dropped.
+    pub fn new(max_buffers: usize) -> BufferPool {
+        BufferPool {
+            buffers: Arc::new(Mutex::new(Vec::with_capacity(max_buffers))),
+            max_buffers,
+        }
+    }
+
+    /// Hands a buffer back, typically the `data` of a packet that is no
+    /// longer needed.
+    pub fn recycle(&self, buf: Vec<u8>) {
+        let mut buffers = self.buffers.lock().unwrap();
+        if buffers.len() < self.max_buffers {
+            buffers.push(buf);
+        }
+    }
+
+    /// Returns the number of buffers available.
+    pub fn len(&self) -> usize {
+        self.buffers.lock().unwrap().len()
+    }
+
+    /// Returns `true` if no buffer is available.
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    /// Copies `data` into a recycled buffer, or a new one if none is left
+    pub(crate) fn copy_of(&self, data: &[u8]) -> Vec<u8> {
+        match self.buffers.lock().unwrap().pop() {
+            Some(mut buf) => {
+                buf.clear();
+                buf.extend_from_slice(data);
+                buf
+            }
+            None => data.to_vec(),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn recycle() {
+        let pool = BufferPool::new(1);
+        let buf = pool.copy_of(&[1, 2, 3]);
+        let ptr = buf.as_ptr();
+
+        pool.recycle(buf);
+        pool.recycle(vec![4]);
+        assert_eq!(pool.len(), 1);
+
+        let buf = pool.copy_of(&[5, 6]);
+        assert_eq!(buf, [5, 6]);
+        assert_eq!(buf.as_ptr(), ptr);
+        assert!(pool.is_empty());
+    }
+}
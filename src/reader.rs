@@ -0,0 +1,675 @@
+//!
+//! Plain `std::io` frame reader, for users that do not need the whole
+//! av-format machinery.
+//!
+//! The header parsing is shared with the demuxer.
+//!
+
+use std::collections::VecDeque;
+use std::io::{self, Read, Seek, SeekFrom};
+
+use log::{debug, warn};
+
+use av_bitstream::byteread::*;
+
+use crate::bitstream::{is_keyframe, obus};
+use crate::common::{IVF_FRAME_HEADER_LEN, IVF_RESERVED_OFFSET};
+use crate::demuxer::{header_size, ivf_header_lenient, IvfFrame, IvfHeader};
+use crate::error::{map_eof, IvfError};
+use crate::index::{is_trailer, read_index, trailer_len};
+use crate::scan::frame_offsets;
+
+/// Reads and parses the IVF header, leaving `r` positioned at the first
+/// frame.
+///
+/// The extra bytes of headers longer than 32 bytes are skipped, and the
+/// legacy 28-byte headers lacking the reserved field are accepted.
+pub fn read_header_only<R: Read>(r: &mut R) -> io::Result<IvfHeader> {
+    let raw = read_raw_header(r)?;
+    match ivf_header_lenient(&raw) {
+        Ok((_, header)) => Ok(header),
+        Err(e) => {
+            debug!("error reading header: {:?}", e);
+            Err(IvfError::InvalidHeader.into())
+        }
+    }
+}
+
+/// Reads the header bytes as stored, as many as its length field declares.
+pub(crate) fn read_raw_header<R: Read>(r: &mut R) -> io::Result<Vec<u8>> {
+    let mut raw = vec![0u8; IVF_RESERVED_OFFSET];
+    r.read_exact(&mut raw)
+        .map_err(|e| map_eof(e, IvfError::TruncatedHeader))?;
+    if !raw.starts_with(b"DKIF") {
+        return Err(IvfError::InvalidHeader.into());
+    }
+    raw.resize(header_size(get_u16l(&raw[6..8])), 0);
+    r.read_exact(&mut raw[IVF_RESERVED_OFFSET..])
+        .map_err(|e| map_eof(e, IvfError::TruncatedHeader))?;
+    Ok(raw)
+}
+
+/// Largest chunk reserved up front for a payload.
+const PAYLOAD_RESERVE: u32 = 1 << 20;
+
+/// Appends a `size` bytes payload to `buf`.
+///
+/// The buffer grows with the data actually read, so a corrupted size
+/// cannot make it allocate more than the stream holds.
+pub(crate) fn read_payload<R: Read>(r: &mut R, size: u32, buf: &mut Vec<u8>) -> io::Result<()> {
+    buf.reserve(size.min(PAYLOAD_RESERVE) as usize);
+    let read = r.take(size as u64).read_to_end(buf)?;
+    if read < size as usize {
+        return Err(IvfError::TruncatedFrame.into());
+    }
+    Ok(())
+}
+
+/// Location of an AV1 OBU within a frame.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct ObuInfo {
+    /// OBU type, as in the OBU header
+    pub obu_type: u8,
+    /// Size in bytes, OBU header included
+    pub size: usize,
+    /// Offset of the OBU header from the start of the frame payload
+    pub offset_in_frame: usize,
+}
+
+/// Lists the OBUs of an AV1 frame payload, stopping at the first one that
+/// does not fit in it.
+pub fn av1_obu_infos(data: &[u8]) -> Vec<ObuInfo> {
+    obus(data)
+        .map(|obu| ObuInfo {
+            obu_type: obu.obu_type,
+            size: obu.len,
+            offset_in_frame: obu.offset,
+        })
+        .collect()
+}
+
+const MAX_RESERVED_FRAMES: usize = 1 << 16;
+
+/// Iterates over the frames of an IVF stream.
+pub struct IvfFrameReader<R> {
+    inner: R,
+    header: IvfHeader,
+    base: u64,
+    peeked: Option<IvfFrame>,
+    raw: Vec<u8>,
+    /// Start of the payload, read while checking for the index trailer
+    lookahead: Vec<u8>,
+}
+
+impl<R: Read> IvfFrameReader<R> {
+    /// Reads the IVF header from `inner` and prepares to read the frames
+    /// following it.
+    pub fn new(mut inner: R) -> io::Result<Self> {
+        let header = read_header_only(&mut inner)?;
+
+        Ok(IvfFrameReader {
+            inner,
+            header,
+            base: 0,
+            peeked: None,
+            raw: Vec::new(),
+            lookahead: Vec::new(),
+        })
+    }
+
+    /// Returns the parsed IVF header.
+    pub fn header(&self) -> &IvfHeader {
+        &self.header
+    }
+
+    /// Returns the next frame without consuming it, the following call to
+    /// `next` returns the same frame.
+    ///
+    /// The frame is read from the underlying reader and kept aside until
+    /// then, so the reader is already past it.
+    pub fn peek(&mut self) -> io::Result<Option<&IvfFrame>> {
+        if self.peeked.is_none() {
+            self.peeked = self.read_frame()?;
+        }
+        Ok(self.peeked.as_ref())
+    }
+
+    /// Unwraps this `IvfFrameReader`, returning the underlying reader.
+    ///
+    /// A frame returned by `peek` and not consumed yet is lost.
+    pub fn into_inner(self) -> R {
+        self.inner
+    }
+
+    /// Reads all the remaining frames, stopping at the first error.
+    pub fn collect_frames(self) -> io::Result<Vec<IvfFrame>> {
+        // The declared count is only a hint, do not trust it blindly
+        let capacity = (self.header.nframe as usize).min(MAX_RESERVED_FRAMES);
+        let mut frames = Vec::with_capacity(capacity);
+        for frame in self {
+            frames.push(frame?);
+        }
+        Ok(frames)
+    }
+
+    /// Turns the reader into an iterator listing the OBUs of each frame,
+    /// for AV1 bitstream analysis.
+    ///
+    /// Only the OBU locations are returned, the payloads are dropped.
+    pub fn obu_infos(self) -> impl Iterator<Item = io::Result<Vec<ObuInfo>>> {
+        self.map(|frame| frame.map(|frame| av1_obu_infos(&frame.data)))
+    }
+
+    /// Turns the reader into an iterator over the groups of pictures: a
+    /// keyframe followed by the frames up to the next keyframe.
+    ///
+    /// The first group starts at the first frame, keyframe or not, a
+    /// warning is logged in the latter case.
+    pub fn gops(self) -> Gops<R> {
+        Gops {
+            reader: self,
+            first: true,
+        }
+    }
+
+    /// Returns the next frame record as stored, the 12 bytes frame header
+    /// followed by the payload, e.g. to copy it verbatim to another file.
+    ///
+    /// The slice borrows an internal buffer, reused by the following calls.
+    pub fn next_raw(&mut self) -> io::Result<Option<&[u8]>> {
+        self.raw.clear();
+        if let Some(frame) = self.peeked.take() {
+            self.raw.extend_from_slice(&frame.size.to_le_bytes());
+            self.raw.extend_from_slice(&frame.timestamp.to_le_bytes());
+            self.raw.extend_from_slice(&frame.data);
+            return Ok(Some(&self.raw));
+        }
+
+        let frame_header = match self.read_frame_header()? {
+            Some(frame_header) => frame_header,
+            None => return Ok(None),
+        };
+        let size = get_u32l(&frame_header[0..4]);
+        self.raw.extend_from_slice(&frame_header);
+        let mut payload = self.lookahead.as_slice().chain(&mut self.inner);
+        read_payload(&mut payload, size, &mut self.raw)?;
+        self.lookahead.clear();
+
+        Ok(Some(&self.raw))
+    }
+
+    /// Reads the payload of the next frame into `buf`, replacing its
+    /// content, and returns the frame timestamp, `None` at the end of the
+    /// frames.
+    ///
+    /// Reusing the same buffer for every frame avoids allocating once it
+    /// is large enough for the largest frame.
+    pub fn read_frame_into(&mut self, buf: &mut Vec<u8>) -> io::Result<Option<u64>> {
+        buf.clear();
+        if let Some(frame) = self.peeked.take() {
+            buf.extend_from_slice(&frame.data);
+            return Ok(Some(frame.timestamp));
+        }
+
+        let frame_header = match self.read_frame_header()? {
+            Some(frame_header) => frame_header,
+            None => return Ok(None),
+        };
+        let size = get_u32l(&frame_header[0..4]);
+        let timestamp = get_u64l(&frame_header[4..12]);
+        let mut payload = self.lookahead.as_slice().chain(&mut self.inner);
+        read_payload(&mut payload, size, buf)?;
+        self.lookahead.clear();
+
+        Ok(Some(timestamp))
+    }
+
+    /// Reads a frame header, `None` at the end of the frames
+    fn read_frame_header(&mut self) -> io::Result<Option<[u8; IVF_FRAME_HEADER_LEN]>> {
+        self.lookahead.clear();
+        let mut frame_header = [0u8; IVF_FRAME_HEADER_LEN];
+        let mut filled = 0;
+        while filled < frame_header.len() {
+            match self.inner.read(&mut frame_header[filled..]) {
+                Ok(0) if filled == 0 => return Ok(None),
+                Ok(0) => return Err(IvfError::TruncatedFrame.into()),
+                Ok(n) => filled += n,
+                Err(e) if e.kind() == io::ErrorKind::Interrupted => {}
+                Err(e) => return Err(e),
+            }
+        }
+
+        // the index trailer is not a frame, once it validates in full
+        let frame_len = IVF_FRAME_HEADER_LEN as u64 + get_u32l(&frame_header[0..4]) as u64;
+        match trailer_len(&frame_header) {
+            Some(len) if len <= frame_len => {
+                let rest = len - IVF_FRAME_HEADER_LEN as u64;
+                let read = (&mut self.inner)
+                    .take(rest)
+                    .read_to_end(&mut self.lookahead)?;
+                if (read as u64) < rest {
+                    return Err(IvfError::TruncatedFrame.into());
+                }
+                let mut trailer = frame_header.to_vec();
+                trailer.extend_from_slice(&self.lookahead);
+                if is_trailer(&trailer) {
+                    self.lookahead.clear();
+                    return Ok(None);
+                }
+            }
+            _ => {}
+        }
+        Ok(Some(frame_header))
+    }
+
+    fn read_frame(&mut self) -> io::Result<Option<IvfFrame>> {
+        let frame_header = match self.read_frame_header()? {
+            Some(frame_header) => frame_header,
+            None => return Ok(None),
+        };
+
+        let size = get_u32l(&frame_header[0..4]);
+        let timestamp = get_u64l(&frame_header[4..12]);
+        let mut data = Vec::new();
+        let mut payload = self.lookahead.as_slice().chain(&mut self.inner);
+        read_payload(&mut payload, size, &mut data)?;
+        self.lookahead.clear();
+
+        Ok(Some(IvfFrame {
+            size,
+            timestamp,
+            data,
+        }))
+    }
+}
+
+impl<R: Read + Seek> IvfFrameReader<R> {
+    /// Reads an IVF stream embedded at `offset` bytes from the start of
+    /// `inner`.
+    ///
+    /// Fails with `InvalidData` if no IVF header is found at that offset.
+    pub fn new_at_offset(mut inner: R, offset: u64) -> io::Result<Self> {
+        inner.seek(SeekFrom::Start(offset))?;
+        let mut reader = Self::new(inner)?;
+        reader.base = offset;
+        Ok(reader)
+    }
+
+    /// Positions the reader on the last frame with a timestamp not greater
+    /// than `timestamp`, using the index trailer.
+    ///
+    /// Returns `false`, leaving the position untouched, if the stream has
+    /// no index trailer or no such frame exists. The trailer is looked for
+    /// at the end of the underlying reader, so a stream opened with
+    /// `new_at_offset` always gets `false`: its end is not known.
+    pub fn seek_to_timestamp(&mut self, timestamp: u64) -> io::Result<bool> {
+        if self.base != 0 {
+            return Ok(false);
+        }
+
+        let pos = self.inner.stream_position()?;
+        let entry = read_index(&mut self.inner)?.and_then(|index| {
+            index
+                .into_iter()
+                .take_while(|entry| entry.timestamp <= timestamp)
+                .last()
+        });
+
+        let target = entry.map_or(pos, |entry| entry.offset);
+        if entry.is_some() {
+            self.peeked = None;
+        }
+        self.inner.seek(SeekFrom::Start(target))?;
+
+        Ok(entry.is_some())
+    }
+
+    /// Turns the reader into an iterator yielding the frames from the last
+    /// one back to the first one, e.g. for reverse playback.
+    ///
+    /// The offset of every frame is collected by scanning the frame headers
+    /// on the first call to `next`, then each step seeks back to the
+    /// previous frame and reads it. Every frame is yielded, including the
+    /// ones already read.
+    pub fn rev(self) -> RevFrames<R> {
+        RevFrames {
+            reader: self,
+            offsets: None,
+        }
+    }
+}
+
+impl IvfFrameReader<ReaderChain> {
+    /// Reads an IVF stream split across `readers`, e.g. the parts of a
+    /// resumed download, as if they were concatenated.
+    ///
+    /// The parts can be split anywhere, even in the middle of a header.
+    pub fn from_chain(readers: Vec<Box<dyn Read>>) -> io::Result<Self> {
+        Self::new(ReaderChain {
+            readers: readers.into(),
+        })
+    }
+}
+
+/// Readers read one after the other, see `IvfFrameReader::from_chain`.
+pub struct ReaderChain {
+    readers: VecDeque<Box<dyn Read>>,
+}
+
+impl Read for ReaderChain {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        while let Some(reader) = self.readers.front_mut() {
+            match reader.read(buf)? {
+                0 if !buf.is_empty() => {
+                    self.readers.pop_front();
+                }
+                n => return Ok(n),
+            }
+        }
+        Ok(0)
+    }
+}
+
+/// Iterator over the frames of an IVF stream in reverse order, see
+/// `IvfFrameReader::rev`.
+pub struct RevFrames<R> {
+    reader: IvfFrameReader<R>,
+    offsets: Option<Vec<u64>>,
+}
+
+impl<R: Read + Seek> Iterator for RevFrames<R> {
+    type Item = io::Result<IvfFrame>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        let reader = &mut self.reader;
+        if self.offsets.is_none() {
+            let offsets = reader
+                .inner
+                .seek(SeekFrom::Start(reader.base))
+                .and_then(|_| frame_offsets(&mut reader.inner));
+            match offsets {
+                Ok(offsets) => self.offsets = Some(offsets),
+                Err(e) => return Some(Err(e)),
+            }
+        }
+
+        let offset = self.offsets.as_mut()?.pop()?;
+        let frame = reader
+            .inner
+            .seek(SeekFrom::Start(offset))
+            .and_then(|_| reader.read_frame());
+        frame.transpose()
+    }
+}
+
+/// Iterator over the groups of pictures of an IVF stream, see
+/// `IvfFrameReader::gops`.
+pub struct Gops<R> {
+    reader: IvfFrameReader<R>,
+    first: bool,
+}
+
+impl<R: Read> Iterator for Gops<R> {
+    type Item = io::Result<Vec<IvfFrame>>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        let codec = self.reader.header.codec;
+        let first = match self.reader.next()? {
+            Ok(frame) => frame,
+            Err(e) => return Some(Err(e)),
+        };
+        if self.first && !is_keyframe(codec, &first.data) {
+            warn!("the stream does not start with a keyframe");
+        }
+        self.first = false;
+
+        let mut gop = vec![first];
+        loop {
+            match self.reader.peek() {
+                Ok(Some(frame)) if !is_keyframe(codec, &frame.data) => {}
+                Ok(_) => return Some(Ok(gop)),
+                Err(e) => return Some(Err(e)),
+            }
+            gop.extend(self.reader.peeked.take());
+        }
+    }
+}
+
+impl<R: Read> Iterator for IvfFrameReader<R> {
+    type Item = io::Result<IvfFrame>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if let Some(frame) = self.peeked.take() {
+            return Some(Ok(frame));
+        }
+        self.read_frame().transpose()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::Cursor;
+
+    use super::*;
+
+    const IVF: &[u8] = include_bytes!("../assets/single_stream_av1.ivf");
+
+    #[test]
+    fn oversized_frame() {
+        // 44 bytes declaring a 4 GiB frame
+        let mut data = crate::test_util::header(b"VP80", 1);
+        data.extend_from_slice(&u32::MAX.to_le_bytes());
+        data.extend_from_slice(&[0; 8]);
+
+        let mut reader = IvfFrameReader::new(Cursor::new(&data)).unwrap();
+        let err = reader.next().unwrap().unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::UnexpectedEof);
+
+        let mut buf = Vec::new();
+        let mut reader = IvfFrameReader::new(Cursor::new(&data)).unwrap();
+        assert!(reader.read_frame_into(&mut buf).is_err());
+        assert!(buf.capacity() <= PAYLOAD_RESERVE as usize);
+
+        let mut reader = IvfFrameReader::new(Cursor::new(&data)).unwrap();
+        assert!(reader.next_raw().is_err());
+    }
+
+    #[test]
+    fn header_len() {
+        let frames = crate::test_util::ivf(b"VP80", &[(0, &[1, 2]), (1, &[3])]);
+
+        let mut long = frames[..32].to_vec();
+        long[6..8].copy_from_slice(&64u16.to_le_bytes());
+        long.extend_from_slice(&[0xff; 32]);
+        long.extend_from_slice(&frames[32..]);
+
+        let mut short = frames[..28].to_vec();
+        short[6..8].copy_from_slice(&28u16.to_le_bytes());
+        short.extend_from_slice(&frames[32..]);
+
+        let expected = IvfFrameReader::new(Cursor::new(&frames))
+            .unwrap()
+            .collect_frames()
+            .unwrap();
+        for data in [long, short] {
+            let reader = IvfFrameReader::new(Cursor::new(&data)).unwrap();
+            assert_eq!(reader.collect_frames().unwrap(), expected);
+            assert_eq!(crate::scan::count_frames(Cursor::new(&data)).unwrap(), 2);
+        }
+
+        let mut truncated = frames[..32].to_vec();
+        truncated[6..8].copy_from_slice(&64u16.to_le_bytes());
+        let err = read_header_only(&mut Cursor::new(&truncated)).unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::UnexpectedEof);
+    }
+
+    #[test]
+    fn header_only() {
+        let mut cursor = Cursor::new(IVF);
+
+        let header = read_header_only(&mut cursor).unwrap();
+        assert_eq!(header.nframe, 25);
+
+        let mut size = [0u8; 4];
+        cursor.read_exact(&mut size).unwrap();
+        assert_eq!(u32::from_le_bytes(size), 0x952);
+    }
+
+    #[test]
+    fn collect_frames() {
+        let frames = IvfFrameReader::new(Cursor::new(IVF))
+            .unwrap()
+            .collect_frames()
+            .unwrap();
+
+        assert_eq!(frames.len(), 25);
+        assert_eq!(frames[0].size as usize, frames[0].data.len());
+    }
+
+    #[test]
+    fn obu_infos() {
+        let mut frames = IvfFrameReader::new(Cursor::new(IVF)).unwrap().obu_infos();
+        let first = frames.next().unwrap().unwrap();
+
+        let obu = |obu_type, size, offset_in_frame| ObuInfo {
+            obu_type,
+            size,
+            offset_in_frame,
+        };
+        // temporal delimiter, sequence header, frame
+        assert_eq!(first, [obu(2, 2, 0), obu(1, 13, 2), obu(6, 2371, 15)]);
+        assert_eq!(frames.count(), 24);
+    }
+
+    #[test]
+    fn peek() {
+        let mut reader = IvfFrameReader::new(Cursor::new(IVF)).unwrap();
+
+        let peeked = reader.peek().unwrap().unwrap().timestamp;
+        assert_eq!(reader.peek().unwrap().unwrap().timestamp, peeked);
+        let first = reader.next().unwrap().unwrap();
+        assert_eq!(first.timestamp, peeked);
+        assert_eq!(first.size, 0x952);
+
+        let second = reader.next().unwrap().unwrap();
+        assert_ne!(second, first);
+        assert_eq!(reader.count(), 23);
+    }
+
+    #[test]
+    fn next_raw() {
+        let mut reader = IvfFrameReader::new(Cursor::new(IVF)).unwrap();
+        let mut frames = IvfFrameReader::new(Cursor::new(IVF)).unwrap();
+        reader.peek().unwrap();
+
+        let mut offset = 32;
+        while let Some(raw) = reader.next_raw().unwrap() {
+            let frame = frames.next().unwrap().unwrap();
+            assert_eq!(raw.len(), 12 + frame.size as usize);
+            assert_eq!(raw[..4], frame.size.to_le_bytes());
+            assert_eq!(raw[4..12], frame.timestamp.to_le_bytes());
+            assert_eq!(raw[12..], frame.data[..]);
+            assert_eq!(raw, &IVF[offset..offset + raw.len()]);
+            offset += raw.len();
+        }
+        assert!(frames.next().is_none());
+        assert_eq!(offset, IVF.len());
+    }
+
+    #[test]
+    fn gops() {
+        const KEY: &[u8] = &[0x50, 0x01, 0x00];
+        const INTER: &[u8] = &[0x51, 0x01, 0x00];
+
+        let keys = [2, 5, 6];
+        let frames = (0..9u64)
+            .map(|i| (i, if keys.contains(&i) { KEY } else { INTER }))
+            .collect::<Vec<_>>();
+        let data = crate::test_util::ivf(b"VP80", &frames);
+
+        let gops = IvfFrameReader::new(Cursor::new(data))
+            .unwrap()
+            .gops()
+            .map(|gop| {
+                gop.unwrap()
+                    .iter()
+                    .map(|frame| frame.timestamp)
+                    .collect::<Vec<_>>()
+            })
+            .collect::<Vec<_>>();
+        assert_eq!(gops, [vec![0, 1], vec![2, 3, 4], vec![5], vec![6, 7, 8]]);
+    }
+
+    #[test]
+    fn from_chain() {
+        let expected = IvfFrameReader::new(Cursor::new(IVF))
+            .unwrap()
+            .collect_frames()
+            .unwrap();
+
+        // in the middle of the header, then of a frame payload
+        for split in [17, 32 + 12 + 100] {
+            let (head, tail) = IVF.split_at(split);
+            let readers: Vec<Box<dyn Read>> = vec![Box::new(head), Box::new(tail)];
+            let frames = IvfFrameReader::from_chain(readers)
+                .unwrap()
+                .collect_frames()
+                .unwrap();
+            assert_eq!(frames, expected);
+        }
+    }
+
+    #[test]
+    fn read_frame_into() {
+        let expected = IvfFrameReader::new(Cursor::new(IVF))
+            .unwrap()
+            .collect_frames()
+            .unwrap();
+        let largest = expected.iter().map(|frame| frame.data.len()).max().unwrap();
+
+        let mut reader = IvfFrameReader::new(Cursor::new(IVF)).unwrap();
+        let mut buf = Vec::with_capacity(largest);
+        let ptr = buf.as_ptr();
+        let mut frames = expected.iter();
+        while let Some(timestamp) = reader.read_frame_into(&mut buf).unwrap() {
+            let frame = frames.next().unwrap();
+            assert_eq!(timestamp, frame.timestamp);
+            assert_eq!(buf, frame.data);
+            assert_eq!(buf.as_ptr(), ptr);
+        }
+        assert!(frames.next().is_none());
+        assert!(buf.is_empty());
+    }
+
+    #[test]
+    fn rev() {
+        let forward = IvfFrameReader::new(Cursor::new(IVF))
+            .unwrap()
+            .collect_frames()
+            .unwrap();
+
+        let mut reader = IvfFrameReader::new(Cursor::new(IVF)).unwrap();
+        reader.next().unwrap().unwrap();
+        let backward = reader.rev().collect::<io::Result<Vec<_>>>().unwrap();
+
+        assert_eq!(backward.len(), forward.len());
+        assert!(backward.iter().eq(forward.iter().rev()));
+    }
+
+    #[test]
+    fn read_at_offset() {
+        let mut data = b"some leading garbage".to_vec();
+        let offset = data.len() as u64;
+        data.extend_from_slice(IVF);
+
+        let reader = IvfFrameReader::new_at_offset(Cursor::new(&data), offset).unwrap();
+        let nframe = reader.header().nframe as usize;
+        let frames = reader.collect::<io::Result<Vec<_>>>().unwrap();
+        assert_eq!(frames.len(), nframe);
+
+        let err = IvfFrameReader::new_at_offset(Cursor::new(&data), 0)
+            .err()
+            .unwrap();
+        assert_eq!(err.kind(), io::ErrorKind::InvalidData);
+    }
+}
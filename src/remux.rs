@@ -0,0 +1,553 @@
+//!
+//! Whole-stream tools built on top of `IvfFrameReader` and
+//! `IvfFrameWriter`.
+//!
+
+use std::fs::File;
+use std::io::{self, BufReader, Read, Seek, SeekFrom, Write};
+use std::path::{Path, PathBuf};
+
+use av_bitstream::byteread::*;
+use av_bitstream::bytewrite::*;
+use av_format::error::*;
+
+use crate::bitstream::is_keyframe;
+use crate::common::{timestamp_to_seconds, Codec, IVF_FRAME_HEADER_LEN};
+use crate::demuxer::{header_size, IvfFrame, IvfHeader};
+use crate::error::IvfError;
+use crate::index::read_index;
+use crate::reader::{read_header_only, read_payload, read_raw_header, IvfFrameReader};
+use crate::scan::count_frames;
+use crate::writer::IvfFrameWriter;
+
+/// Returns the bytes written to an in-memory writer, with the frame count
+/// filled in.
+fn finish_in_memory(writer: IvfFrameWriter<Vec<u8>>) -> Vec<u8> {
+    let nframe = writer.frame_count();
+    let mut data = writer.into_inner();
+    put_u32l(&mut data[24..28], nframe);
+    data
+}
+
+/// Splits `src` into keyframe aligned segments, each a complete IVF file
+/// with its own header.
+///
+/// A new segment starts at the first keyframe found once `target_duration`
+/// seconds have elapsed since the start of the current one. `on_segment` is
+/// called with the segment number and its bytes.
+pub fn segment<R: Read + Seek>(
+    src: R,
+    target_duration: f64,
+    mut on_segment: impl FnMut(usize, &[u8]),
+) -> Result<()> {
+    let reader = IvfFrameReader::new(src)?;
+    let header = IvfHeader {
+        nframe: 0,
+        ..reader.header().clone()
+    };
+    let codec = header.codec;
+    let timebase = header.timebase();
+
+    let mut index = 0;
+    let mut start = None;
+    let mut writer = IvfFrameWriter::new(Vec::new(), &header)?;
+
+    for frame in reader {
+        let frame = frame?;
+        let elapsed = start
+            .and_then(|start| {
+                let ts = frame.timestamp.checked_sub(start)?;
+                timestamp_to_seconds(ts, timebase)
+            })
+            .unwrap_or(0.0);
+
+        if start.is_some() && elapsed >= target_duration && is_keyframe(codec, &frame.data) {
+            let done = std::mem::replace(&mut writer, IvfFrameWriter::new(Vec::new(), &header)?);
+            on_segment(index, &finish_in_memory(done));
+            index += 1;
+            start = None;
+        }
+
+        start.get_or_insert(frame.timestamp);
+        writer.write_frame(frame.timestamp, &frame.data)?;
+    }
+
+    if writer.frame_count() > 0 {
+        on_segment(index, &finish_in_memory(writer));
+    }
+
+    Ok(())
+}
+
+/// Copies only the keyframes of `src` to `dst`, producing an all-intra
+/// preview in which every frame is a seek point.
+///
+/// Timestamps are kept, bumped where needed so they stay strictly
+/// increasing. The frame count in the header is patched once done, and
+/// returned.
+pub fn keyframes_only_remux<R: Read, W: Write + Seek>(src: R, mut dst: W) -> Result<u32> {
+    let reader = IvfFrameReader::new(src)?;
+    let header = IvfHeader {
+        nframe: 0,
+        ..reader.header().clone()
+    };
+    let codec = header.codec;
+
+    let start = dst.stream_position()?;
+    let mut writer = IvfFrameWriter::new(dst, &header)?;
+    let mut last = None;
+    for frame in reader {
+        let frame = frame?;
+        if !is_keyframe(codec, &frame.data) {
+            continue;
+        }
+
+        let timestamp = match last {
+            Some(last) if frame.timestamp <= last => last + 1,
+            _ => frame.timestamp,
+        };
+        writer.write_frame(timestamp, &frame.data)?;
+        last = Some(timestamp);
+    }
+
+    finish_seekable(writer, start)
+}
+
+/// Fills in the frame count of a header written at `start`, returning it.
+fn finish_seekable<W: Write + Seek>(writer: IvfFrameWriter<W>, start: u64) -> Result<u32> {
+    let nframe = writer.frame_count();
+    let mut dst = writer.into_inner();
+    let end = dst.stream_position()?;
+    let mut count = [0u8; 4];
+    put_u32l(&mut count, nframe);
+    dst.seek(SeekFrom::Start(start + 24))?;
+    dst.write_all(&count)?;
+    dst.seek(SeekFrom::Start(end))?;
+
+    Ok(nframe)
+}
+
+/// Copies the frames of `src` for which `keep` returns `true` to `dst`,
+/// e.g. to cut a clip out of a longer stream.
+///
+/// Timestamps are rebased so the first frame kept starts at zero, and the
+/// frame count in the header is patched once done.
+pub fn remux_filter<R: Read, W: Write + Seek>(
+    src: R,
+    mut dst: W,
+    mut keep: impl FnMut(&IvfFrame) -> bool,
+) -> Result<()> {
+    let reader = IvfFrameReader::new(src)?;
+    let header = IvfHeader {
+        nframe: 0,
+        ..reader.header().clone()
+    };
+
+    let start = dst.stream_position()?;
+    let mut writer = IvfFrameWriter::new(dst, &header)?;
+    let mut origin = None;
+    for frame in reader {
+        let frame = frame?;
+        if !keep(&frame) {
+            continue;
+        }
+
+        let origin = *origin.get_or_insert(frame.timestamp);
+        let timestamp = frame.timestamp.saturating_sub(origin);
+        writer.write_frame(timestamp, &frame.data)?;
+    }
+
+    finish_seekable(writer, start)?;
+    Ok(())
+}
+
+/// Stitches `inputs` together into a single stream written to `dst`.
+///
+/// The header of the first input is used, every other input must share its
+/// codec, dimensions and timebase or `InvalidInput` is returned before
+/// anything is written. Each input is shifted to start one frame duration,
+/// as measured between its last two frames, after the last frame of the
+/// previous one. The frame count in the header is patched once done.
+pub fn concat<W: Write + Seek>(mut dst: W, inputs: &[PathBuf]) -> Result<()> {
+    let mut headers = Vec::with_capacity(inputs.len());
+    for path in inputs {
+        let header = read_header_only(&mut File::open(path)?)?;
+        if let Some(first) = headers.first() {
+            check_compatible(first, &header, path)?;
+        }
+        headers.push(header);
+    }
+    let header = match headers.first() {
+        Some(first) => IvfHeader {
+            nframe: 0,
+            ..first.clone()
+        },
+        None => return Err(io::Error::new(io::ErrorKind::InvalidInput, "no input").into()),
+    };
+
+    let start = dst.stream_position()?;
+    let mut writer = IvfFrameWriter::new(dst, &header)?;
+    let mut next = 0;
+    for path in inputs {
+        let reader = IvfFrameReader::new(BufReader::new(File::open(path)?))?;
+        let mut origin = None;
+        let mut last = None;
+        let mut duration = 1;
+        for frame in reader {
+            let frame = frame?;
+            let origin = *origin.get_or_insert(frame.timestamp);
+            let timestamp = next + frame.timestamp.saturating_sub(origin);
+            if let Some(last) = last {
+                duration = timestamp.saturating_sub(last).max(1);
+            }
+            writer.write_frame(timestamp, &frame.data)?;
+            last = Some(timestamp);
+        }
+        if let Some(last) = last {
+            next = last + duration;
+        }
+    }
+
+    finish_seekable(writer, start)?;
+    Ok(())
+}
+
+/// Copies the IVF file at `temp_path` to `final_path`, filling in the
+/// frame count of the copy, and returns that count.
+///
+/// Meant for streams first written to a temporary file with a placeholder
+/// header, e.g. by a muxer that cannot seek back. The frames are counted
+/// from their headers, the temporary file is left untouched.
+pub fn finalize_streaming<P: AsRef<Path>, Q: AsRef<Path>>(
+    temp_path: P,
+    final_path: Q,
+) -> Result<u32> {
+    let nframe = count_frames(File::open(&temp_path)?)?;
+
+    let mut src = BufReader::new(File::open(&temp_path)?);
+    let mut header = read_raw_header(&mut src)?;
+    put_u32l(&mut header[24..28], nframe);
+
+    let mut dst = io::BufWriter::new(File::create(final_path)?);
+    dst.write_all(&header)?;
+    io::copy(&mut src, &mut dst)?;
+    dst.flush()?;
+
+    Ok(nframe)
+}
+
+/// Fails with `InvalidInput` unless `other` can be appended to `first`.
+fn check_compatible(first: &IvfHeader, other: &IvfHeader, path: &Path) -> io::Result<()> {
+    let mismatch = if other.codec != first.codec {
+        "codec"
+    } else if (other.width, other.height) != (first.width, first.height) {
+        "dimensions"
+    } else if other.timebase() != first.timebase() {
+        "timebase"
+    } else {
+        return Ok(());
+    };
+
+    Err(io::Error::new(
+        io::ErrorKind::InvalidInput,
+        format!(
+            "{}: {} differs from the first input",
+            path.display(),
+            mismatch
+        ),
+    ))
+}
+
+/// Picks `n` keyframes evenly spaced across `r`, e.g. to decode thumbnails
+/// for a contact sheet, returning all of them if there are fewer.
+///
+/// Keyframes are taken from the index trailer if present, otherwise every
+/// payload is read once to detect them. Only the frames returned are kept
+/// in memory.
+pub fn sample_keyframes<R: Read + Seek>(mut r: R, n: usize) -> Result<Vec<IvfFrame>> {
+    let start = r.stream_position()?;
+    let end = r.seek(SeekFrom::End(0))?;
+    let index = read_index(&mut r)?;
+    r.seek(SeekFrom::Start(start))?;
+
+    let keyframes = match index {
+        Some(index) => index
+            .iter()
+            .filter(|entry| entry.is_key)
+            .map(|entry| start + entry.offset)
+            .collect(),
+        None => {
+            let reader = IvfFrameReader::new(&mut r)?;
+            let codec = reader.header().codec;
+            let mut offset = start + header_size(reader.header().header_len) as u64;
+            let mut keyframes = Vec::new();
+            for frame in reader {
+                let frame = frame?;
+                if is_keyframe(codec, &frame.data) {
+                    keyframes.push(offset);
+                }
+                offset += (IVF_FRAME_HEADER_LEN + frame.data.len()) as u64;
+            }
+            keyframes
+        }
+    };
+
+    let count = n.min(keyframes.len());
+    let mut frames = Vec::with_capacity(count);
+    for i in 0..count {
+        let offset = keyframes[i * keyframes.len() / count];
+        r.seek(SeekFrom::Start(offset))?;
+        let mut frame_header = [0u8; IVF_FRAME_HEADER_LEN];
+        r.read_exact(&mut frame_header)?;
+        let size = get_u32l(&frame_header[0..4]);
+        if offset + (IVF_FRAME_HEADER_LEN as u64) + size as u64 > end {
+            return Err(io::Error::from(IvfError::TruncatedFrame).into());
+        }
+        let mut data = Vec::new();
+        read_payload(&mut r, size, &mut data)?;
+        frames.push(IvfFrame {
+            size,
+            timestamp: get_u64l(&frame_header[4..12]),
+            data,
+        });
+    }
+
+    Ok(frames)
+}
+
+/// Writes the payloads of `src` to `dst` as a raw elementary stream, each
+/// frame prefixed by its length as a 32-bit big-endian integer.
+///
+/// The IVF header and the timestamps are dropped.
+pub fn to_length_prefixed<R: Read, W: Write>(src: R, mut dst: W) -> Result<()> {
+    for frame in IvfFrameReader::new(src)? {
+        let frame = frame?;
+        dst.write_all(&frame.size.to_be_bytes())?;
+        dst.write_all(&frame.data)?;
+    }
+    dst.flush()?;
+    Ok(())
+}
+
+/// Writes the payload of every frame of `src` to a file of its own in
+/// `dir`, named `{prefix}_{n}` with an extension depending on the codec,
+/// e.g. `frame_0.obu` for AV1. Returns the number of files written.
+///
+/// The payloads are written raw, without any IVF framing.
+pub fn explode<R: Read>(src: R, dir: &Path, prefix: &str) -> Result<usize> {
+    let reader = IvfFrameReader::new(src)?;
+    let extension = match reader.header().codec {
+        Codec::VP8 => "vp8",
+        Codec::VP9 => "vp9",
+        Codec::AV1 => "obu",
+    };
+
+    let mut count = 0;
+    for frame in reader {
+        let frame = frame?;
+        let path = dir.join(format!("{}_{}.{}", prefix, count, extension));
+        std::fs::write(path, &frame.data)?;
+        count += 1;
+    }
+    Ok(count)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::Cursor;
+
+    use super::*;
+
+    const KEY: &[u8] = &[0x50, 0x01, 0x00, 0x9d, 0x01, 0x2a, 0x40, 0x01, 0xf0, 0x00];
+    const INTER: &[u8] = &[0x51, 0x01, 0x00, 0x00];
+
+    #[test]
+    fn segments_start_on_keyframes() {
+        let frames = (0..12u64)
+            .map(|i| (i, if i % 3 == 0 { KEY } else { INTER }))
+            .collect::<Vec<_>>();
+        let data = crate::test_util::ivf(b"VP80", &frames);
+
+        let mut segments = Vec::new();
+        segment(Cursor::new(data), 0.15, |index, data| {
+            segments.push((index, data.to_vec()))
+        })
+        .unwrap();
+
+        assert_eq!(segments.len(), 2);
+        for (i, (index, data)) in segments.iter().enumerate() {
+            assert_eq!(i, *index);
+            let reader = IvfFrameReader::new(Cursor::new(data)).unwrap();
+            assert_eq!(reader.header().nframe, 6);
+            let frames = reader.collect_frames().unwrap();
+            assert_eq!(frames.len(), 6);
+            assert!(is_keyframe(Codec::VP8, &frames[0].data));
+            assert_eq!(frames[0].timestamp, i as u64 * 6);
+        }
+    }
+
+    #[test]
+    fn keyframes_only() {
+        let frames = (0..10u64)
+            .map(|i| (i, if i % 4 == 0 { KEY } else { INTER }))
+            .collect::<Vec<_>>();
+        let data = crate::test_util::ivf(b"VP80", &frames);
+
+        let mut out = Cursor::new(Vec::new());
+        let count = keyframes_only_remux(Cursor::new(data), &mut out).unwrap();
+        assert_eq!(count, 3);
+
+        let reader = IvfFrameReader::new(Cursor::new(out.into_inner())).unwrap();
+        assert_eq!(reader.header().nframe, 3);
+        let frames = reader.collect_frames().unwrap();
+        assert!(frames.len() < 10);
+        assert!(frames.iter().all(|f| is_keyframe(Codec::VP8, &f.data)));
+        let timestamps = frames.iter().map(|f| f.timestamp).collect::<Vec<_>>();
+        assert_eq!(timestamps, [0, 4, 8]);
+    }
+
+    #[test]
+    fn length_prefixed() {
+        let frames: &[(u64, &[u8])] = &[(0, KEY), (1, INTER), (2, &[])];
+        let data = crate::test_util::ivf(b"VP80", frames);
+
+        let mut out = Vec::new();
+        to_length_prefixed(Cursor::new(data), &mut out).unwrap();
+
+        let mut payloads = Vec::new();
+        let mut rest = &out[..];
+        while !rest.is_empty() {
+            let len = u32::from_be_bytes([rest[0], rest[1], rest[2], rest[3]]) as usize;
+            payloads.push(&rest[4..4 + len]);
+            rest = &rest[4 + len..];
+        }
+        assert_eq!(payloads, frames.iter().map(|f| f.1).collect::<Vec<_>>());
+    }
+
+    #[test]
+    fn explode() {
+        const IVF: &[u8] = include_bytes!("../assets/single_stream_av1.ivf");
+        let dir = tempfile::tempdir().unwrap();
+
+        let count = super::explode(Cursor::new(IVF), dir.path(), "frame").unwrap();
+
+        assert_eq!(count, 25);
+        assert_eq!(std::fs::read_dir(dir.path()).unwrap().count(), 25);
+        let frames = IvfFrameReader::new(Cursor::new(IVF)).unwrap();
+        for (n, frame) in frames.enumerate() {
+            let path = dir.path().join(format!("frame_{}.obu", n));
+            assert_eq!(std::fs::read(path).unwrap(), frame.unwrap().data);
+        }
+    }
+
+    #[test]
+    fn concat_two_clips() {
+        let dir = tempfile::tempdir().unwrap();
+        let first = dir.path().join("first.ivf");
+        let second = dir.path().join("second.ivf");
+        let frames = (0..3u64).map(|i| (i * 2, INTER)).collect::<Vec<_>>();
+        std::fs::write(&first, crate::test_util::ivf(b"VP80", &frames)).unwrap();
+        let frames = (0..3u64).map(|i| (100 + i * 2, INTER)).collect::<Vec<_>>();
+        std::fs::write(&second, crate::test_util::ivf(b"VP80", &frames)).unwrap();
+
+        let mut out = Cursor::new(Vec::new());
+        concat(&mut out, &[first.clone(), second]).unwrap();
+
+        let reader = IvfFrameReader::new(Cursor::new(out.into_inner())).unwrap();
+        assert_eq!(reader.header().nframe, 6);
+        let timestamps = reader
+            .map(|frame| frame.unwrap().timestamp)
+            .collect::<Vec<_>>();
+        assert_eq!(timestamps, [0, 2, 4, 6, 8, 10]);
+
+        let other = dir.path().join("other.ivf");
+        std::fs::write(&other, crate::test_util::ivf(b"VP90", &[(0, &[0x82])])).unwrap();
+        let mut out = Cursor::new(Vec::new());
+        let err = concat(&mut out, &[first, other]).unwrap_err();
+        assert!(matches!(err, Error::Io(e) if e.kind() == io::ErrorKind::InvalidInput));
+        assert!(out.into_inner().is_empty());
+    }
+
+    #[test]
+    fn sample_keyframes() {
+        let frames = (0..40u64)
+            .map(|i| (i, if i % 4 == 0 { KEY } else { INTER }))
+            .collect::<Vec<_>>();
+        let data = crate::test_util::ivf(b"VP80", &frames);
+
+        let sampled = super::sample_keyframes(Cursor::new(&data), 3).unwrap();
+        let timestamps = sampled.iter().map(|f| f.timestamp).collect::<Vec<_>>();
+        assert_eq!(timestamps, [0, 12, 24]);
+        assert!(sampled.iter().all(|f| f.data == KEY));
+
+        let sampled = super::sample_keyframes(Cursor::new(&data), 50).unwrap();
+        assert_eq!(sampled.len(), 10);
+
+        let mut builder = crate::writer::IvfBuilder::new(Codec::VP8, 64, 48);
+        builder.set_write_index(true);
+        for &(ts, data) in &frames {
+            builder.frame(ts, ts % 8 == 0, data);
+        }
+        let mut data = builder.build();
+        let sampled = super::sample_keyframes(Cursor::new(&data), 2).unwrap();
+        let timestamps = sampled.iter().map(|f| f.timestamp).collect::<Vec<_>>();
+        assert_eq!(timestamps, [0, 16]);
+
+        // a corrupted size is caught before allocating the payload
+        data[32..36].copy_from_slice(&u32::MAX.to_le_bytes());
+        let err = super::sample_keyframes(Cursor::new(&data), 2).unwrap_err();
+        assert!(matches!(err, Error::Io(e) if e.kind() == io::ErrorKind::UnexpectedEof));
+    }
+
+    #[test]
+    fn finalize_streaming() {
+        use crate::muxer::IvfMuxer;
+        use av_data::packet::Packet;
+        use av_format::muxer::{Muxer, Writer};
+        use std::sync::Arc;
+
+        let dir = tempfile::tempdir().unwrap();
+        let temp = dir.path().join("capture.ivf.part");
+        let done = dir.path().join("capture.ivf");
+
+        let mut muxer = IvfMuxer::new();
+        let mut writer = Writer::new(File::create(&temp).unwrap());
+        muxer.write_placeholder_header(&mut writer).unwrap();
+        for i in 0..5 {
+            let mut pkt = Packet::zeroed(3);
+            pkt.t.pts = Some(i);
+            muxer.write_packet(&mut writer, Arc::new(pkt)).unwrap();
+        }
+        muxer.write_trailer(&mut writer).unwrap();
+        drop(writer);
+
+        assert_eq!(super::finalize_streaming(&temp, &done).unwrap(), 5);
+        let reader = IvfFrameReader::new(File::open(&done).unwrap()).unwrap();
+        assert_eq!(reader.header().nframe, 5);
+        assert_eq!(reader.count(), 5);
+
+        let original = std::fs::read(&temp).unwrap();
+        let finalized = std::fs::read(&done).unwrap();
+        assert_eq!(original[..24], finalized[..24]);
+        assert_eq!(original[28..], finalized[28..]);
+    }
+
+    #[test]
+    fn filter_range() {
+        let frames = (0..10u64).map(|i| (i * 2, INTER)).collect::<Vec<_>>();
+        let data = crate::test_util::ivf(b"VP80", &frames);
+
+        let mut out = Cursor::new(Vec::new());
+        remux_filter(Cursor::new(data), &mut out, |frame| {
+            (6..=12).contains(&frame.timestamp)
+        })
+        .unwrap();
+
+        let reader = IvfFrameReader::new(Cursor::new(out.into_inner())).unwrap();
+        assert_eq!(reader.header().nframe, 4);
+        let frames = reader.collect_frames().unwrap();
+        assert_eq!(frames.len(), 4);
+        assert_eq!(frames[0].timestamp, 0);
+        assert_eq!(frames[3].timestamp, 6);
+    }
+}
@@ -0,0 +1,170 @@
+//!
+//! Fast scans over the frame headers of a seekable IVF stream.
+//!
+//! Payloads are skipped by seeking past them, they are never read.
+//!
+
+use std::io::{self, Read, Seek, SeekFrom};
+
+use av_bitstream::byteread::*;
+use av_format::error::*;
+
+use crate::common::IVF_FRAME_HEADER_LEN;
+use crate::index::{is_trailer, trailer_len};
+use crate::reader::read_header_only;
+
+/// Walks the frames of `r`, calling `on_frame` with the absolute offset of
+/// each frame header and the payload size, then `progress` with the bytes
+/// walked so far and the stream length.
+fn scan<R: Read + Seek>(
+    mut r: R,
+    mut on_frame: impl FnMut(u64, u32),
+    mut progress: impl FnMut(u64, u64),
+) -> io::Result<()> {
+    read_header_only(&mut r)?;
+    let mut offset = r.stream_position()?;
+    let end = r.seek(SeekFrom::End(0))?;
+
+    while offset < end {
+        let mut head = [0u8; 8];
+        r.seek(SeekFrom::Start(offset))?;
+        r.read_exact(&mut head)?;
+
+        let size = get_u32l(&head[0..4]);
+        let next = offset + IVF_FRAME_HEADER_LEN as u64 + size as u64;
+
+        // the index trailer is not a frame, once it validates in full
+        match trailer_len(&head) {
+            Some(len) if offset + len == end && offset + len <= next => {
+                let mut trailer = vec![0u8; len as usize];
+                r.seek(SeekFrom::Start(offset))?;
+                r.read_exact(&mut trailer)?;
+                if is_trailer(&trailer) {
+                    break;
+                }
+            }
+            _ => {}
+        }
+
+        if next > end {
+            return Err(io::ErrorKind::UnexpectedEof.into());
+        }
+
+        on_frame(offset, size);
+        progress(next, end);
+        offset = next;
+    }
+
+    Ok(())
+}
+
+/// Returns the `[start, end)` byte range of every frame record, frame
+/// header included.
+pub fn frame_ranges<R: Read + Seek>(r: R) -> Result<Vec<(u64, u64)>> {
+    frame_ranges_with_progress(r, |_, _| {})
+}
+
+/// Like `frame_ranges`, calling `progress` with the number of bytes
+/// processed and the total length of `r` after every frame.
+pub fn frame_ranges_with_progress<R: Read + Seek>(
+    r: R,
+    progress: impl FnMut(u64, u64),
+) -> Result<Vec<(u64, u64)>> {
+    let mut ranges = Vec::new();
+    scan(
+        r,
+        |offset, size| ranges.push((offset, offset + IVF_FRAME_HEADER_LEN as u64 + size as u64)),
+        progress,
+    )?;
+    Ok(ranges)
+}
+
+/// Returns the absolute offset of every frame header.
+pub(crate) fn frame_offsets<R: Read + Seek>(r: R) -> io::Result<Vec<u64>> {
+    let mut offsets = Vec::new();
+    scan(r, |offset, _| offsets.push(offset), |_, _| {})?;
+    Ok(offsets)
+}
+
+/// Counts the frames of `r` without reading their payloads.
+pub fn count_frames<R: Read + Seek>(r: R) -> Result<u32> {
+    count_frames_with_progress(r, |_, _| {})
+}
+
+/// Like `count_frames`, calling `progress` with the number of bytes
+/// processed and the total length of `r` after every frame, e.g. to drive
+/// a progress bar on huge files.
+pub fn count_frames_with_progress<R: Read + Seek>(
+    r: R,
+    progress: impl FnMut(u64, u64),
+) -> Result<u32> {
+    let mut count = 0;
+    scan(r, |_, _| count += 1, progress)?;
+    Ok(count)
+}
+
+/// Returns the size of the largest frame payload of `r`, 0 if it holds no
+/// frame, e.g. to allocate a buffer fitting any frame up front.
+pub fn max_frame_size<R: Read + Seek>(r: R) -> Result<u32> {
+    let mut max = 0;
+    scan(r, |_, size| max = max.max(size), |_, _| {})?;
+    Ok(max)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::Cursor;
+
+    use super::*;
+
+    const IVF: &[u8] = include_bytes!("../assets/single_stream_av1.ivf");
+
+    #[test]
+    fn count() {
+        let header = read_header_only(&mut Cursor::new(IVF)).unwrap();
+        assert_eq!(count_frames(Cursor::new(IVF)).unwrap(), header.nframe);
+
+        let truncated = &IVF[..IVF.len() - 1];
+        assert!(count_frames(Cursor::new(truncated)).is_err());
+
+        let mut truncated_size = IVF.to_vec();
+        truncated_size.extend_from_slice(&[1, 0]);
+        assert!(count_frames(Cursor::new(truncated_size)).is_err());
+    }
+
+    #[test]
+    fn max_frame_size() {
+        use crate::reader::IvfFrameReader;
+
+        let expected = IvfFrameReader::new(Cursor::new(IVF))
+            .unwrap()
+            .map(|frame| frame.unwrap().size)
+            .max()
+            .unwrap();
+        assert_eq!(super::max_frame_size(Cursor::new(IVF)).unwrap(), expected);
+        assert_eq!(super::max_frame_size(Cursor::new(&IVF[..32])).unwrap(), 0);
+    }
+
+    #[test]
+    fn progress() {
+        let mut calls = Vec::new();
+        let count =
+            count_frames_with_progress(Cursor::new(IVF), |done, total| calls.push((done, total)))
+                .unwrap();
+
+        assert_eq!(calls.len(), count as usize);
+        assert!(calls.windows(2).all(|pair| pair[0].0 < pair[1].0));
+        assert!(calls.iter().all(|&(_, total)| total == IVF.len() as u64));
+        assert_eq!(calls.last().unwrap().0, IVF.len() as u64);
+    }
+
+    #[test]
+    fn ranges_cover_the_file() {
+        let ranges = frame_ranges(Cursor::new(IVF)).unwrap();
+
+        assert_eq!(ranges.len(), 25);
+        assert_eq!(ranges[0].0, 32);
+        assert_eq!(ranges[24].1, IVF.len() as u64);
+        assert!(ranges.windows(2).all(|pair| pair[0].1 == pair[1].0));
+    }
+}
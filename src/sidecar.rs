@@ -0,0 +1,119 @@
+//!
+//! Per-frame metadata stored next to an IVF file.
+//!
+//! IVF has no room for frame metadata, such as capture timestamps, so
+//! this crate defines a sidecar file laid out as
+//!
+//! - the `IVFM` magic
+//! - for each frame carrying metadata, its index in the stream as u32,
+//!   the metadata length as u32 and the metadata bytes
+//!
+//! All values are little-endian. See `IvfDemuxer::set_sidecar` to attach
+//! the metadata to the demuxed packets.
+//!
+
+use std::collections::HashMap;
+use std::io::{self, Read, Write};
+
+use av_bitstream::byteread::*;
+use av_bitstream::bytewrite::*;
+
+/// Magic starting a sidecar file.
+pub const SIDECAR_MAGIC: &[u8; 4] = b"IVFM";
+
+/// Metadata of a single frame, attached to `Packet::t.user_private`.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct FrameMetadata(pub Vec<u8>);
+
+/// Writes a sidecar holding `entries`, as `(frame index, metadata)`.
+pub fn write_sidecar<W: Write>(w: &mut W, entries: &[(u32, &[u8])]) -> io::Result<()> {
+    w.write_all(SIDECAR_MAGIC)?;
+    for &(index, data) in entries {
+        let mut record = [0u8; 8];
+        put_u32l(&mut record[0..4], index);
+        put_u32l(&mut record[4..8], data.len() as u32);
+        w.write_all(&record)?;
+        w.write_all(data)?;
+    }
+    Ok(())
+}
+
+/// Reads a whole sidecar, mapping frame indices to their metadata.
+///
+/// Fails with `InvalidData` if the magic is missing, and with
+/// `UnexpectedEof` if the last record is truncated.
+pub fn read_sidecar<R: Read>(r: &mut R) -> io::Result<HashMap<u32, Vec<u8>>> {
+    let mut data = Vec::new();
+    r.read_to_end(&mut data)?;
+    if !data.starts_with(SIDECAR_MAGIC) {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidData,
+            "missing sidecar magic",
+        ));
+    }
+
+    let mut entries = HashMap::new();
+    let mut rest = &data[SIDECAR_MAGIC.len()..];
+    while !rest.is_empty() {
+        if rest.len() < 8 {
+            return Err(io::ErrorKind::UnexpectedEof.into());
+        }
+        let index = get_u32l(&rest[0..4]);
+        let len = get_u32l(&rest[4..8]) as usize;
+        let metadata = rest.get(8..8 + len).ok_or(io::ErrorKind::UnexpectedEof)?;
+        entries.insert(index, metadata.to_vec());
+        rest = &rest[8 + len..];
+    }
+
+    Ok(entries)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::Cursor;
+
+    use av_format::buffer::AccReader;
+    use av_format::demuxer::{Context, Event};
+
+    use super::*;
+    use crate::demuxer::IvfDemuxer;
+
+    #[test]
+    fn attach_metadata() {
+        let mut sidecar = tempfile::NamedTempFile::new().unwrap();
+        write_sidecar(&mut sidecar, &[(0, b"first"), (2, b"third")]).unwrap();
+
+        let frames: &[(u64, &[u8])] = &[(0, &[0x50]), (1, &[0x51]), (2, &[0x51])];
+        let data = crate::test_util::ivf(b"VP80", frames);
+        let mut ivf_demuxer = IvfDemuxer::new();
+        ivf_demuxer.set_sidecar(sidecar.path()).unwrap();
+        let mut demuxer = Context::new(ivf_demuxer, AccReader::new(Cursor::new(data)));
+        demuxer.read_headers().unwrap();
+
+        let mut metadata = Vec::new();
+        while let Event::NewPacket(pkt) = demuxer.read_event().unwrap() {
+            let private = pkt.t.user_private.as_ref();
+            metadata.push(private.and_then(|p| p.downcast_ref::<FrameMetadata>().cloned()));
+        }
+        assert_eq!(
+            metadata,
+            [
+                Some(FrameMetadata(b"first".to_vec())),
+                None,
+                Some(FrameMetadata(b"third".to_vec())),
+            ]
+        );
+    }
+
+    #[test]
+    fn truncated() {
+        let mut data = Vec::new();
+        write_sidecar(&mut data, &[(0, b"first")]).unwrap();
+        assert_eq!(read_sidecar(&mut &data[..]).unwrap()[&0], b"first");
+
+        let err = read_sidecar(&mut &data[..data.len() - 1]).unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::UnexpectedEof);
+        let err = read_sidecar(&mut &data[1..]).unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::InvalidData);
+    }
+}
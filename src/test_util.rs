@@ -0,0 +1,61 @@
+//! Helpers to build synthetic IVF streams in unit tests.
+
+use crate::common::Codec;
+use crate::writer::IvfBuilder;
+
+/// Builds an IVF stream out of a FourCC and a list of
+/// `(timestamp, payload)` frames.
+pub(crate) fn ivf(fourcc: &[u8; 4], frames: &[(u64, &[u8])]) -> Vec<u8> {
+    let mut out = header(fourcc, frames.len() as u32);
+    for (timestamp, data) in frames {
+        out.extend_from_slice(&(data.len() as u32).to_le_bytes());
+        out.extend_from_slice(&timestamp.to_le_bytes());
+        out.extend_from_slice(data);
+    }
+    out
+}
+
+/// Builds a 64x48, 30 fps IVF header declaring `nframe` frames.
+pub(crate) fn header(fourcc: &[u8; 4], nframe: u32) -> Vec<u8> {
+    let mut out = IvfBuilder::new(Codec::VP8, 64, 48)
+        .set_nframe(nframe)
+        .build();
+    out[8..12].copy_from_slice(fourcc);
+    out
+}
+
+/// Builds the uncompressed header of a profile 0 VP9 keyframe, signalling
+/// `render` as render size if given.
+pub(crate) fn vp9_keyframe(width: u16, height: u16, render: Option<(u16, u16)>) -> Vec<u8> {
+    let mut bits = Vec::new();
+    let mut push = |value: u32, len: usize| {
+        bits.extend((0..len).rev().map(|i| (value >> i) & 1 == 1));
+    };
+
+    // frame marker, profile 0, show_existing_frame, key frame, show_frame,
+    // error_resilient_mode
+    push(0b1000_0010, 8);
+    push(0x498342, 24);
+    // BT.601, studio range
+    push(1, 3);
+    push(0, 1);
+    push(width as u32 - 1, 16);
+    push(height as u32 - 1, 16);
+    match render {
+        Some((width, height)) => {
+            push(1, 1);
+            push(width as u32 - 1, 16);
+            push(height as u32 - 1, 16);
+        }
+        None => push(0, 1),
+    }
+
+    bits.chunks(8)
+        .map(|chunk| {
+            chunk
+                .iter()
+                .enumerate()
+                .fold(0u8, |byte, (i, &bit)| byte | (bit as u8) << (7 - i))
+        })
+        .collect()
+}
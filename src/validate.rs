@@ -0,0 +1,722 @@
+//!
+//! Walk a whole IVF stream and report on its structure.
+//!
+
+use std::io::{self, Cursor, Read};
+
+use crate::bitstream::{coded_size, is_keyframe};
+use crate::common::{timestamp_to_seconds, Codec, IVF_FRAME_HEADER_LEN, IVF_HEADER_LEN};
+use crate::demuxer::IvfHeader;
+use crate::error::IvfError;
+use crate::reader::IvfFrameReader;
+
+/// Summary of an IVF stream.
+#[derive(Clone, Debug)]
+pub struct IvfReport {
+    /// The parsed header
+    pub header: IvfHeader,
+    /// Number of frames actually present in the stream
+    pub frame_count: u32,
+    /// Number of frames with an empty payload
+    ///
+    /// For AV1 these usually carry a show-existing-frame.
+    pub zero_byte_frames: u32,
+    /// Keyframe cadence
+    pub gops: GopStats,
+    /// Indices of the non-empty frames whose payload is identical to the
+    /// one of the previous frame, e.g. static content in screen recordings
+    pub duplicate_frames: Vec<u32>,
+    /// Likely dropped frames, as `(index, expected_ts, actual_ts)`
+    ///
+    /// A gap is reported for every frame that follows the previous one by
+    /// more than 1.5 times the median frame duration, the expected
+    /// timestamp being the previous one plus that median.
+    pub gaps: Vec<(u32, u64, u64)>,
+    /// Whether seeking can land on keyframes
+    ///
+    /// `true` if the stream holds at least one keyframe, keyframes being
+    /// detected from the bitstream of every supported codec.
+    pub seekable: bool,
+    /// Fraction of frames that are keyframes, between 0 and 1
+    ///
+    /// Hints at the seek granularity: 1 for an all-intra stream, close to
+    /// 0 if only the first frame is a keyframe.
+    pub keyframe_density: f64,
+    /// Coded size changes, as `(index, width, height)`
+    ///
+    /// Sizes are parsed from the frames signalling them, keyframes and
+    /// AV1 sequence headers, and compared to the previous one, starting
+    /// from the header dimensions.
+    pub resolution_changes: Vec<(u32, u32, u32)>,
+    /// Issues found by the optional checks of `ValidateOptions`
+    pub warnings: Vec<IvfWarning>,
+    /// Whether the timestamps ever go back
+    pub timestamp_order: TimestampOrder,
+    /// Best guess at the tool that wrote the stream, from header quirks
+    ///
+    /// This is a heuristic: `None` means no known quirk was found, not
+    /// that the writer is unknown, and a match may be a coincidence.
+    pub writer_hint: Option<String>,
+}
+
+/// Order of the frame timestamps, hinting at whether a reorder buffer is
+/// needed downstream.
+///
+/// IVF does not say whether timestamps follow the decoding or the display
+/// order, monotonic timestamps only make the display order likely.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum TimestampOrder {
+    /// The timestamps never decrease
+    Monotonic,
+    /// Some timestamp is lower than the previous one
+    Reordered,
+    /// Fewer than two frames
+    Unknown,
+}
+
+/// Suspicious but readable content reported by `validate_with`.
+#[derive(Clone, Debug, PartialEq, Eq)]
+#[non_exhaustive]
+pub enum IvfWarning {
+    /// The frame does not follow the previous one by exactly one tick of
+    /// the header timebase, as constant frame rate content would
+    IrregularCadence {
+        /// Frame index
+        index: u32,
+        /// Distance in ticks from the previous timestamp, `None` if it
+        /// went backwards
+        delta: Option<u64>,
+    },
+    /// The frame is too short to hold the headers of its codec, a sign of
+    /// corruption
+    ImplausiblySmallFrame {
+        /// Frame index
+        index: u32,
+        /// Payload size in bytes
+        size: u32,
+    },
+    /// The reserved header bytes, which the specification requires to be
+    /// zero, are not
+    NonZeroReserved {
+        /// The bytes at offset 28, in file order
+        bytes: [u8; 4],
+    },
+}
+
+/// Optional checks and limits for `validate_with`.
+#[derive(Clone, Debug, Default)]
+pub struct ValidateOptions {
+    /// Fail with `IvfError::TooManyFrames` past this many frames
+    pub max_frames: Option<u32>,
+    /// Report `IvfWarning::IrregularCadence` for every frame not one tick
+    /// after the previous one
+    pub check_cadence: bool,
+    /// Report `IvfWarning::ImplausiblySmallFrame` for every non-empty frame
+    /// shorter than the headers of its codec
+    ///
+    /// Empty frames are left alone: libvpx writes them for dropped frames.
+    pub check_frame_sizes: bool,
+    /// Report deviations from the specification that readers tolerate,
+    /// such as `IvfWarning::NonZeroReserved`
+    pub strict: bool,
+}
+
+/// Statistics on the distance between keyframes.
+///
+/// The intervals are measured between consecutive keyframes, so they are
+/// `None` unless the stream holds at least two of them.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct GopStats {
+    /// Number of groups of pictures, one per keyframe
+    pub count: u32,
+    /// Average keyframe interval in frames
+    pub avg_interval_frames: Option<f64>,
+    /// Longest keyframe interval in frames
+    pub max_interval_frames: Option<u32>,
+    /// Average keyframe interval in seconds
+    pub avg_interval_seconds: Option<f64>,
+    /// Longest keyframe interval in seconds
+    pub max_interval_seconds: Option<f64>,
+}
+
+/// Reads a whole IVF stream and reports on its content.
+///
+/// The legacy `AOM1` FourCC is accepted as AV1.
+pub fn validate<R: Read>(r: R) -> io::Result<IvfReport> {
+    validate_with(r, &ValidateOptions::default())
+}
+
+/// Like `validate`, but fails with `IvfError::TooManyFrames` as soon as
+/// the stream turns out to hold more than `max_frames` frames.
+///
+/// Meant for untrusted input, where a file could declare billions of tiny
+/// frames.
+pub fn validate_with_max_frames<R: Read>(r: R, max_frames: u32) -> io::Result<IvfReport> {
+    let options = ValidateOptions {
+        max_frames: Some(max_frames),
+        ..ValidateOptions::default()
+    };
+    validate_with(r, &options)
+}
+
+/// Like `validate`, also running the checks enabled in `options`.
+pub fn validate_with<R: Read>(r: R, options: &ValidateOptions) -> io::Result<IvfReport> {
+    validate_with_progress(r, options, |_, _| {})
+}
+
+/// Like `validate_with`, calling `progress` with the number of bytes
+/// processed after every frame.
+///
+/// The length of a mere `Read` is unknown, so the total passed along is
+/// always 0.
+pub fn validate_with_progress<R: Read>(
+    mut r: R,
+    options: &ValidateOptions,
+    mut progress: impl FnMut(u64, u64),
+) -> io::Result<IvfReport> {
+    let max_frames = options.max_frames.unwrap_or(u32::MAX);
+    let mut raw = Vec::with_capacity(IVF_HEADER_LEN);
+    r.by_ref()
+        .take(IVF_HEADER_LEN as u64)
+        .read_to_end(&mut raw)?;
+    let writer_hint = writer_hint(&raw);
+    if raw.get(8..12) == Some(LEGACY_AV1_FOURCC) {
+        // same bitstream, only the FourCC predates the AV1 release
+        raw[8..12].copy_from_slice(Codec::AV1.fourcc());
+    }
+    let reader = IvfFrameReader::new(Cursor::new(raw).chain(r))?;
+    let header = reader.header().clone();
+    let timebase = header.timebase();
+
+    let mut frame_count = 0;
+    let mut zero_byte_frames = 0;
+    let mut gops = GopStats::default();
+    let mut last_key: Option<(u32, u64)> = None;
+    let mut total_frames = 0u64;
+    let mut total_seconds = 0.0;
+    let mut timestamps = Vec::new();
+    let mut warnings = Vec::new();
+    if options.strict && header.reserved != 0 {
+        warnings.push(IvfWarning::NonZeroReserved {
+            bytes: header.reserved.to_le_bytes(),
+        });
+    }
+    let mut resolution_changes = Vec::new();
+    let mut size = (header.width as u32, header.height as u32);
+    let mut duplicate_frames = Vec::new();
+    let mut previous: Option<Vec<u8>> = None;
+    let mut processed = header.header_len as u64;
+    for frame in reader {
+        let frame = frame?;
+        if frame_count == max_frames {
+            return Err(IvfError::TooManyFrames(max_frames).into());
+        }
+        frame_count += 1;
+        processed += (IVF_FRAME_HEADER_LEN + frame.data.len()) as u64;
+        progress(processed, 0);
+        match timestamps.last() {
+            Some(&last) if options.check_cadence => {
+                let delta = frame.timestamp.checked_sub(last);
+                if delta != Some(1) {
+                    warnings.push(IvfWarning::IrregularCadence {
+                        index: frame_count - 1,
+                        delta,
+                    });
+                }
+            }
+            _ => {}
+        }
+        timestamps.push(frame.timestamp);
+        if options.check_frame_sizes && frame.size > 0 && frame.size < min_frame_size(header.codec)
+        {
+            warnings.push(IvfWarning::ImplausiblySmallFrame {
+                index: frame_count - 1,
+                size: frame.size,
+            });
+        }
+        if frame.size == 0 {
+            zero_byte_frames += 1;
+        }
+
+        match coded_size(header.codec, &frame.data) {
+            Some(coded) if coded != size => {
+                resolution_changes.push((frame_count - 1, coded.0, coded.1));
+                size = coded;
+            }
+            _ => {}
+        }
+
+        if is_keyframe(header.codec, &frame.data) {
+            let index = frame_count - 1;
+            if let Some((last_index, last_ts)) = last_key {
+                let frames = index - last_index;
+                total_frames += frames as u64;
+                gops.max_interval_frames = gops.max_interval_frames.max(Some(frames));
+
+                let seconds = frame
+                    .timestamp
+                    .checked_sub(last_ts)
+                    .and_then(|ts| timestamp_to_seconds(ts, timebase));
+                if let Some(seconds) = seconds {
+                    total_seconds += seconds;
+                    let max = gops
+                        .max_interval_seconds
+                        .map_or(seconds, |max| max.max(seconds));
+                    gops.max_interval_seconds = Some(max);
+                }
+            }
+            last_key = Some((index, frame.timestamp));
+            gops.count += 1;
+        }
+
+        if !frame.data.is_empty() && previous.as_ref() == Some(&frame.data) {
+            duplicate_frames.push(frame_count - 1);
+        }
+        previous = Some(frame.data);
+    }
+
+    if gops.count > 1 {
+        let intervals = (gops.count - 1) as f64;
+        gops.avg_interval_frames = Some(total_frames as f64 / intervals);
+        if gops.max_interval_seconds.is_some() {
+            gops.avg_interval_seconds = Some(total_seconds / intervals);
+        }
+    }
+
+    let keyframe_density = if frame_count > 0 {
+        gops.count as f64 / frame_count as f64
+    } else {
+        0.0
+    };
+
+    Ok(IvfReport {
+        header,
+        frame_count,
+        zero_byte_frames,
+        seekable: gops.count > 0,
+        keyframe_density,
+        gops,
+        duplicate_frames,
+        gaps: timestamp_gaps(&timestamps),
+        resolution_changes,
+        warnings,
+        timestamp_order: timestamp_order(&timestamps),
+        writer_hint,
+    })
+}
+
+fn timestamp_order(timestamps: &[u64]) -> TimestampOrder {
+    if timestamps.len() < 2 {
+        TimestampOrder::Unknown
+    } else if timestamps.windows(2).all(|pair| pair[0] <= pair[1]) {
+        TimestampOrder::Monotonic
+    } else {
+        TimestampOrder::Reordered
+    }
+}
+
+/// FourCC written for AV1 by the av-ivf muxer before the bitstream freeze
+const LEGACY_AV1_FOURCC: &[u8; 4] = b"AOM1";
+
+/// Guesses the writer from the raw header bytes
+fn writer_hint(raw: &[u8]) -> Option<String> {
+    if raw.len() < IVF_HEADER_LEN {
+        return None;
+    }
+    if &raw[8..12] == LEGACY_AV1_FOURCC {
+        return Some("legacy av-ivf muxer (AOM1 FourCC)".to_owned());
+    }
+    // FFmpeg reserves the frame count with all ones and only fills it in
+    // if the output is seekable
+    if raw[24..32].iter().all(|&b| b == 0xff) {
+        return Some("FFmpeg, unseekable output".to_owned());
+    }
+    None
+}
+
+/// Smallest frame a decoder can make sense of
+fn min_frame_size(codec: Codec) -> u32 {
+    match codec {
+        // frame tag
+        Codec::VP8 => 3,
+        // a show-existing-frame header fits in a single byte
+        Codec::VP9 => 1,
+        // OBU header and size
+        Codec::AV1 => 2,
+    }
+}
+
+/// Finds the timestamp jumps larger than 1.5 times the median delta
+fn timestamp_gaps(timestamps: &[u64]) -> Vec<(u32, u64, u64)> {
+    let deltas = timestamps
+        .windows(2)
+        .map(|pair| pair[1].checked_sub(pair[0]))
+        .collect::<Vec<_>>();
+
+    let mut sorted = deltas.iter().flatten().copied().collect::<Vec<_>>();
+    sorted.sort_unstable();
+    let median = match sorted.get(sorted.len() / 2) {
+        Some(&median) if median > 0 => median as u128,
+        _ => return Vec::new(),
+    };
+
+    deltas
+        .iter()
+        .zip(timestamps.windows(2))
+        .enumerate()
+        .filter_map(|(i, (delta, pair))| {
+            let delta = (*delta)? as u128;
+            (delta * 2 > median * 3).then(|| {
+                let expected = pair[0].saturating_add(median as u64);
+                (i as u32 + 1, expected, pair[1])
+            })
+        })
+        .collect()
+}
+
+/// Returns `true` if `a` and `b` hold the same frames for the same codec
+/// and dimensions, whatever their timestamps and timebases.
+pub fn payloads_equal<A: Read, B: Read>(a: A, b: B) -> io::Result<bool> {
+    let mut a = IvfFrameReader::new(a)?;
+    let mut b = IvfFrameReader::new(b)?;
+
+    let (header_a, header_b) = (a.header(), b.header());
+    if header_a.codec != header_b.codec
+        || header_a.width != header_b.width
+        || header_a.height != header_b.height
+    {
+        return Ok(false);
+    }
+
+    loop {
+        match (a.next().transpose()?, b.next().transpose()?) {
+            (Some(frame_a), Some(frame_b)) if frame_a.data == frame_b.data => {}
+            (None, None) => return Ok(true),
+            _ => return Ok(false),
+        }
+    }
+}
+
+/// Hashes the parts of an IVF stream that identify its content, for
+/// deduplication.
+///
+/// The codec, dimensions, timebase and every frame size, timestamp and
+/// payload are covered, while the frame count, the reserved field and the
+/// other header fields are not, so a copy whose count was patched in
+/// afterwards fingerprints the same. The hash, 64-bit FNV-1a, is stable
+/// across platforms and versions of this crate, but is not cryptographic.
+pub fn content_fingerprint<R: Read>(r: R) -> io::Result<u64> {
+    let reader = IvfFrameReader::new(r)?;
+    let header = reader.header();
+
+    let mut hash = Fnv1a::default();
+    hash.update(header.codec.fourcc());
+    hash.update(&header.width.to_le_bytes());
+    hash.update(&header.height.to_le_bytes());
+    hash.update(&header.rate.to_le_bytes());
+    hash.update(&header.scale.to_le_bytes());
+    for frame in reader {
+        let frame = frame?;
+        hash.update(&frame.size.to_le_bytes());
+        hash.update(&frame.timestamp.to_le_bytes());
+        hash.update(&frame.data);
+    }
+    Ok(hash.0)
+}
+
+/// 64-bit FNV-1a
+struct Fnv1a(u64);
+
+impl Default for Fnv1a {
+    fn default() -> Fnv1a {
+        Fnv1a(0xcbf2_9ce4_8422_2325)
+    }
+}
+
+impl Fnv1a {
+    fn update(&mut self, data: &[u8]) {
+        for &byte in data {
+            self.0 = (self.0 ^ byte as u64).wrapping_mul(0x0100_0000_01b3);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::Cursor;
+
+    use super::*;
+    use crate::test_util::ivf;
+
+    #[test]
+    fn zero_byte_frames() {
+        let data = ivf(b"AV01", &[(0, &[0x12, 0x00]), (1, &[]), (2, &[0x12, 0x00])]);
+
+        let report = validate(Cursor::new(data)).unwrap();
+
+        assert_eq!(report.frame_count, 3);
+        assert_eq!(report.zero_byte_frames, 1);
+    }
+
+    #[test]
+    fn progress() {
+        let data = ivf(b"VP80", &[(0, &[0x50, 1]), (1, &[0x51]), (2, &[])]);
+
+        let mut calls = Vec::new();
+        validate_with_progress(
+            Cursor::new(&data),
+            &ValidateOptions::default(),
+            |done, total| calls.push((done, total)),
+        )
+        .unwrap();
+
+        assert_eq!(calls, [(46, 0), (59, 0), (71, 0)]);
+        assert_eq!(calls.last().unwrap().0, data.len() as u64);
+    }
+
+    #[test]
+    fn gop_stats() {
+        const KEY: &[u8] = &[0x50, 0x01, 0x00, 0x9d, 0x01, 0x2a, 0x40, 0x01, 0xf0, 0x00];
+        const INTER: &[u8] = &[0x51, 0x01, 0x00, 0x00];
+
+        let frames = (0..75u64)
+            .map(|i| (i, if i % 30 == 0 { KEY } else { INTER }))
+            .collect::<Vec<_>>();
+
+        let report = validate(Cursor::new(ivf(b"VP80", &frames))).unwrap();
+
+        assert_eq!(
+            report.gops,
+            GopStats {
+                count: 3,
+                avg_interval_frames: Some(30.0),
+                max_interval_frames: Some(30),
+                avg_interval_seconds: Some(1.0),
+                max_interval_seconds: Some(1.0),
+            }
+        );
+    }
+
+    #[test]
+    fn seekable() {
+        const KEY: &[u8] = &[0x50, 0x01, 0x00];
+        const INTER: &[u8] = &[0x51, 0x01, 0x00];
+
+        let intra = ivf(b"VP80", &[(0, KEY), (1, KEY), (2, KEY), (3, KEY)]);
+        let report = validate(Cursor::new(intra)).unwrap();
+        assert!(report.seekable);
+        assert_eq!(report.keyframe_density, 1.0);
+
+        let single = ivf(b"VP80", &[(0, KEY), (1, INTER), (2, INTER), (3, INTER)]);
+        let report = validate(Cursor::new(single)).unwrap();
+        assert!(report.seekable);
+        assert_eq!(report.keyframe_density, 0.25);
+
+        let none = ivf(b"VP80", &[(0, INTER), (1, INTER)]);
+        let report = validate(Cursor::new(none)).unwrap();
+        assert!(!report.seekable);
+        assert_eq!(report.keyframe_density, 0.0);
+    }
+
+    #[test]
+    fn resolution_changes() {
+        const SMALL: &[u8] = &[0x50, 0x01, 0x00, 0x9d, 0x01, 0x2a, 0x40, 0x00, 0x30, 0x00];
+        const LARGE: &[u8] = &[0x50, 0x01, 0x00, 0x9d, 0x01, 0x2a, 0x80, 0x00, 0x60, 0x00];
+        const INTER: &[u8] = &[0x51, 0x01, 0x00];
+
+        let frames = [(0, SMALL), (1, INTER), (2, SMALL), (3, LARGE), (4, INTER)];
+        let report = validate(Cursor::new(ivf(b"VP80", &frames))).unwrap();
+
+        assert_eq!(report.resolution_changes, [(3, 128, 96)]);
+    }
+
+    #[test]
+    fn payloads_equal() {
+        let a = ivf(b"VP80", &[(0, &[0x50, 1]), (1, &[0x51])]);
+        let b = ivf(b"VP80", &[(0, &[0x50, 1]), (3000, &[0x51])]);
+        let c = ivf(b"VP80", &[(0, &[0x50, 2]), (1, &[0x51])]);
+        let d = ivf(b"VP80", &[(0, &[0x50, 1])]);
+
+        assert!(super::payloads_equal(&a[..], &b[..]).unwrap());
+        assert!(!super::payloads_equal(&a[..], &c[..]).unwrap());
+        assert!(!super::payloads_equal(&a[..], &d[..]).unwrap());
+        assert!(!super::payloads_equal(&d[..], &a[..]).unwrap());
+    }
+
+    #[test]
+    fn gaps() {
+        let data = ivf(
+            b"VP80",
+            &[
+                (0, &[0x50]),
+                (2, &[0x51]),
+                (4, &[0x51]),
+                (8, &[0x51]),
+                (10, &[0x51]),
+            ],
+        );
+
+        let report = validate(Cursor::new(data)).unwrap();
+
+        assert_eq!(report.gaps, [(3, 6, 8)]);
+    }
+
+    #[test]
+    fn cadence() {
+        let options = ValidateOptions {
+            check_cadence: true,
+            ..ValidateOptions::default()
+        };
+
+        let cfr = ivf(b"VP80", &[(0, &[0x50]), (1, &[0x51]), (2, &[0x51])]);
+        let report = validate_with(Cursor::new(cfr), &options).unwrap();
+        assert!(report.warnings.is_empty());
+
+        let vfr = ivf(
+            b"VP80",
+            &[(0, &[0x50]), (1, &[0x51]), (3, &[0x51]), (2, &[0x51])],
+        );
+        let report = validate(Cursor::new(&vfr)).unwrap();
+        assert!(report.warnings.is_empty());
+        let report = validate_with(Cursor::new(&vfr), &options).unwrap();
+        assert_eq!(
+            report.warnings,
+            [
+                IvfWarning::IrregularCadence {
+                    index: 2,
+                    delta: Some(2)
+                },
+                IvfWarning::IrregularCadence {
+                    index: 3,
+                    delta: None
+                },
+            ]
+        );
+    }
+
+    #[test]
+    fn timestamp_order() {
+        let order = |timestamps: &[u64]| {
+            let frames = timestamps
+                .iter()
+                .map(|&ts| (ts, &[0x51u8][..]))
+                .collect::<Vec<_>>();
+            validate(Cursor::new(ivf(b"VP80", &frames)))
+                .unwrap()
+                .timestamp_order
+        };
+
+        assert_eq!(order(&[0, 1, 1, 2, 3]), TimestampOrder::Monotonic);
+        assert_eq!(order(&[0, 3, 1, 2, 6]), TimestampOrder::Reordered);
+        assert_eq!(order(&[0]), TimestampOrder::Unknown);
+    }
+
+    #[test]
+    fn writer_hint() {
+        let report = validate(Cursor::new(ivf(b"AV01", &[(0, &[0x12, 0x00])]))).unwrap();
+        assert_eq!(report.writer_hint, None);
+
+        let report = validate(Cursor::new(ivf(b"AOM1", &[(0, &[0x12, 0x00])]))).unwrap();
+        assert_eq!(report.header.codec, Codec::AV1);
+        assert_eq!(report.frame_count, 1);
+        let hint = report.writer_hint.unwrap();
+        assert!(hint.contains("legacy av-ivf muxer"), "{}", hint);
+    }
+
+    #[test]
+    fn content_fingerprint() {
+        let frames: &[(u64, &[u8])] = &[(0, &[0x50, 1]), (1, &[0x51])];
+        let data = ivf(b"VP80", frames);
+        let mut patched = data.clone();
+        patched[24..28].copy_from_slice(&0u32.to_le_bytes());
+        let other = ivf(b"VP80", &[(0, &[0x50, 1]), (2, &[0x51])]);
+
+        let fingerprint = super::content_fingerprint(&data[..]).unwrap();
+        assert_ne!(data, patched);
+        assert_eq!(
+            super::content_fingerprint(&patched[..]).unwrap(),
+            fingerprint
+        );
+        assert_ne!(super::content_fingerprint(&other[..]).unwrap(), fingerprint);
+    }
+
+    #[test]
+    fn small_frames() {
+        let options = ValidateOptions {
+            check_frame_sizes: true,
+            ..ValidateOptions::default()
+        };
+        let data = ivf(
+            b"VP80",
+            &[
+                (0, &[0x50, 0x01, 0x00]),
+                (1, &[0x51]),
+                (2, &[]),
+                (3, &[0x51, 0x01, 0x00]),
+            ],
+        );
+
+        let report = validate(Cursor::new(&data)).unwrap();
+        assert!(report.warnings.is_empty());
+        let report = validate_with(Cursor::new(&data), &options).unwrap();
+        assert_eq!(
+            report.warnings,
+            [IvfWarning::ImplausiblySmallFrame { index: 1, size: 1 }]
+        );
+    }
+
+    #[test]
+    fn non_zero_reserved() {
+        let options = ValidateOptions {
+            strict: true,
+            ..ValidateOptions::default()
+        };
+        let mut data = ivf(b"VP80", &[(0, &[0x50, 0x01, 0x00])]);
+        let report = validate_with(Cursor::new(&data), &options).unwrap();
+        assert!(report.warnings.is_empty());
+
+        data[28..32].copy_from_slice(b"ab\0c");
+        let report = validate(Cursor::new(&data)).unwrap();
+        assert!(report.warnings.is_empty());
+        let report = validate_with(Cursor::new(&data), &options).unwrap();
+        assert_eq!(
+            report.warnings,
+            [IvfWarning::NonZeroReserved { bytes: *b"ab\0c" }]
+        );
+    }
+
+    #[test]
+    fn duplicate_frames() {
+        const A: &[u8] = &[0x50, 0x01, 0x00];
+        const B: &[u8] = &[0x51, 0x01, 0x00];
+        let data = ivf(
+            b"VP80",
+            &[
+                (0, A),
+                (1, A),
+                (2, B),
+                (3, B),
+                (4, B),
+                (5, &[]),
+                (6, &[]),
+                (7, A),
+            ],
+        );
+
+        let report = validate(Cursor::new(data)).unwrap();
+        assert_eq!(report.duplicate_frames, [1, 3, 4]);
+    }
+
+    #[test]
+    fn max_frames() {
+        let data = ivf(b"VP80", &[(0, &[0x50]), (1, &[0x51]), (2, &[0x51])]);
+
+        let report = validate_with_max_frames(Cursor::new(&data), 3).unwrap();
+        assert_eq!(report.frame_count, 3);
+
+        let err = validate_with_max_frames(Cursor::new(&data), 2).unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::InvalidData);
+        let inner = err.get_ref().unwrap().downcast_ref::<IvfError>();
+        assert!(matches!(inner, Some(IvfError::TooManyFrames(2))));
+    }
+}
@@ -0,0 +1,442 @@
+//!
+//! Plain `std::io` frame writer, the counterpart of `IvfFrameReader`.
+//!
+
+use std::io::{self, Write};
+
+use av_bitstream::bytewrite::*;
+use av_data::rational::Rational64;
+
+use crate::common::{Codec, Timestamp, IVF_FRAME_HEADER_LEN, TIMESTAMP_KEYFRAME_FLAG};
+use crate::demuxer::IvfHeader;
+use crate::index::{write_index, IndexEntry};
+
+/// Writes an IVF header followed by frames.
+pub struct IvfFrameWriter<W> {
+    inner: W,
+    frame_count: u32,
+    timebase: Rational64,
+}
+
+impl<W: Write> IvfFrameWriter<W> {
+    /// Writes `header` to `inner` and prepares to write frames after it.
+    pub fn new(mut inner: W, header: &IvfHeader) -> io::Result<Self> {
+        inner.write_all(&header.to_bytes())?;
+        Ok(IvfFrameWriter {
+            inner,
+            frame_count: 0,
+            timebase: header.timebase(),
+        })
+    }
+
+    /// Writes a single frame.
+    pub fn write_frame(&mut self, timestamp: u64, data: &[u8]) -> io::Result<()> {
+        let mut frame_header = [0u8; IVF_FRAME_HEADER_LEN];
+        put_u32l(&mut frame_header[0..4], data.len() as u32);
+        put_u64l(&mut frame_header[4..12], timestamp);
+        self.inner.write_all(&frame_header)?;
+        self.inner.write_all(data)?;
+        self.frame_count += 1;
+        Ok(())
+    }
+
+    /// Writes a single frame, converting `timestamp` to the header timebase.
+    ///
+    /// Fails with `InvalidInput` if the timestamp cannot be represented.
+    pub fn write_frame_at(&mut self, timestamp: Timestamp, data: &[u8]) -> io::Result<()> {
+        let timestamp = timestamp
+            .rescale(self.timebase)
+            .ok_or_else(|| io::Error::new(io::ErrorKind::InvalidInput, "timestamp out of range"))?;
+        self.write_frame(timestamp.value, data)
+    }
+
+    /// Writes the frames packed back to back in `blob`, `sizes` giving the
+    /// length of each of them.
+    ///
+    /// The first frame gets `first_ts`, each following one `ts_step` more.
+    /// Fails with `InvalidInput`, writing nothing, if the sizes do not add
+    /// up to the blob length or the last timestamp overflows.
+    pub fn write_from_blob(
+        &mut self,
+        blob: &[u8],
+        sizes: &[u32],
+        first_ts: u64,
+        ts_step: u64,
+    ) -> io::Result<()> {
+        let total = sizes.iter().map(|&size| size as u64).sum::<u64>();
+        if total != blob.len() as u64 {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                "frame sizes do not match the blob length",
+            ));
+        }
+
+        let steps = sizes.len().saturating_sub(1) as u64;
+        let last_ts = steps
+            .checked_mul(ts_step)
+            .and_then(|span| first_ts.checked_add(span));
+        if last_ts.is_none() {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                "frame timestamps out of range",
+            ));
+        }
+
+        let mut rest = blob;
+        for (i, &size) in sizes.iter().enumerate() {
+            let (data, tail) = rest.split_at(size as usize);
+            self.write_frame(first_ts + i as u64 * ts_step, data)?;
+            rest = tail;
+        }
+        Ok(())
+    }
+
+    /// Returns the number of frames written so far.
+    pub fn frame_count(&self) -> u32 {
+        self.frame_count
+    }
+
+    /// Unwraps this `IvfFrameWriter`, returning the underlying writer.
+    pub fn into_inner(self) -> W {
+        self.inner
+    }
+}
+
+/// Writes a one frame stream, e.g. an AV1 still image.
+///
+/// The header declares a single frame at 1 fps, the frame gets timestamp 0.
+pub fn write_single_frame<W: Write>(
+    w: W,
+    codec: Codec,
+    width: u16,
+    height: u16,
+    data: &[u8],
+) -> io::Result<()> {
+    let header = IvfHeader {
+        version: 0,
+        header_len: 32,
+        width,
+        height,
+        rate: 1,
+        scale: 1,
+        codec,
+        nframe: 1,
+        reserved: 0,
+    };
+    let mut writer = IvfFrameWriter::new(w, &header)?;
+    writer.write_frame(0, data)?;
+    writer.inner.flush()
+}
+
+/// Builds IVF streams in memory, e.g. as test fixtures.
+///
+/// The frames are written as given, so edge cases such as empty payloads
+/// or repeated timestamps can be crafted. The header declares the number
+/// of frames added unless `set_nframe` overrides it.
+///
+/// ```
+/// use std::io::Cursor;
+///
+/// use av_format::buffer::AccReader;
+/// use av_ivf::common::Codec;
+/// use av_ivf::demuxer::{Context, Event, IvfDemuxer};
+/// use av_ivf::writer::IvfBuilder;
+///
+/// let mut builder = IvfBuilder::new(Codec::VP8, 64, 48);
+/// builder
+///     .frame(0, true, &[0x50, 0x01, 0x00])
+///     .frame(1, false, &[0x51, 0x01, 0x00]);
+/// let data = builder.build();
+///
+/// let mut demuxer = Context::new(IvfDemuxer::new(), AccReader::new(Cursor::new(data)));
+/// demuxer.read_headers().unwrap();
+/// let mut timestamps = Vec::new();
+/// while let Event::NewPacket(pkt) = demuxer.read_event().unwrap() {
+///     timestamps.push(pkt.t.pts.unwrap());
+/// }
+/// assert_eq!(timestamps, [0, 1]);
+/// ```
+#[derive(Clone, Debug)]
+pub struct IvfBuilder {
+    header: IvfHeader,
+    nframe: Option<u32>,
+    frames: Vec<(u64, bool, Vec<u8>)>,
+    keyframe_in_timestamp_msb: bool,
+    write_index: bool,
+}
+
+impl IvfBuilder {
+    /// Creates a builder for a 30 fps stream.
+    pub fn new(codec: Codec, width: u16, height: u16) -> IvfBuilder {
+        IvfBuilder::with_header(IvfHeader {
+            version: 0,
+            header_len: 32,
+            width,
+            height,
+            rate: 30,
+            scale: 1,
+            codec,
+            nframe: 0,
+            reserved: 0,
+        })
+    }
+
+    /// Creates a builder writing `header`, frame count aside, as is.
+    pub fn with_header(header: IvfHeader) -> IvfBuilder {
+        IvfBuilder {
+            header,
+            nframe: None,
+            frames: Vec::new(),
+            keyframe_in_timestamp_msb: false,
+            write_index: false,
+        }
+    }
+
+    /// Declare `nframe` frames in the header, whatever the actual count.
+    pub fn set_nframe(&mut self, nframe: u32) -> &mut Self {
+        self.nframe = Some(nframe);
+        self
+    }
+
+    /// Flag keyframes in the most significant bit of their timestamp.
+    pub fn set_keyframe_in_timestamp_msb(&mut self, enabled: bool) -> &mut Self {
+        self.keyframe_in_timestamp_msb = enabled;
+        self
+    }
+
+    /// Append an index trailer, see the `index` module.
+    pub fn set_write_index(&mut self, enabled: bool) -> &mut Self {
+        self.write_index = enabled;
+        self
+    }
+
+    /// Adds a frame.
+    ///
+    /// `is_key` is only stored if keyframes are flagged in the timestamps
+    /// or in an index trailer.
+    pub fn frame(&mut self, timestamp: u64, is_key: bool, data: &[u8]) -> &mut Self {
+        self.frames.push((timestamp, is_key, data.to_vec()));
+        self
+    }
+
+    /// Returns the bytes of the stream.
+    pub fn build(&self) -> Vec<u8> {
+        let header = IvfHeader {
+            nframe: self.nframe.unwrap_or(self.frames.len() as u32),
+            ..self.header.clone()
+        };
+
+        let mut index = Vec::with_capacity(self.frames.len());
+        // writing to a Vec cannot fail
+        let mut writer = IvfFrameWriter::new(Vec::new(), &header).unwrap();
+        for (timestamp, is_key, data) in &self.frames {
+            index.push(IndexEntry {
+                timestamp: *timestamp,
+                offset: writer.inner.len() as u64,
+                is_key: *is_key,
+            });
+            let timestamp = match is_key {
+                true if self.keyframe_in_timestamp_msb => timestamp | TIMESTAMP_KEYFRAME_FLAG,
+                _ => *timestamp,
+            };
+            writer.write_frame(timestamp, data).unwrap();
+        }
+
+        let mut data = writer.into_inner();
+        if self.write_index {
+            write_index(&mut data, &index).unwrap();
+        }
+        data
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::Cursor;
+
+    use super::*;
+    use crate::reader::{read_header_only, IvfFrameReader};
+
+    #[test]
+    fn write_from_blob() {
+        let header = read_header_only(&mut &crate::test_util::header(b"VP80", 3)[..]).unwrap();
+        let mut writer = IvfFrameWriter::new(Vec::new(), &header).unwrap();
+
+        let blob = [1, 2, 3, 4, 5, 6];
+        let err = writer.write_from_blob(&blob, &[1, 2], 0, 1).unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::InvalidInput);
+        assert_eq!(writer.frame_count(), 0);
+
+        let err = writer
+            .write_from_blob(&blob, &[1, 2, 3], u64::MAX - 2, 2)
+            .unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::InvalidInput);
+        assert_eq!(writer.frame_count(), 0);
+        // the timestamp after the last frame is never computed
+        let mut last = IvfFrameWriter::new(Vec::new(), &header).unwrap();
+        last.write_from_blob(&blob, &[3, 3], u64::MAX - 2, 2)
+            .unwrap();
+
+        writer.write_from_blob(&blob, &[1, 2, 3], 10, 2).unwrap();
+        assert_eq!(writer.frame_count(), 3);
+
+        let frames = IvfFrameReader::new(Cursor::new(writer.into_inner()))
+            .unwrap()
+            .map(|frame| {
+                let frame = frame.unwrap();
+                (frame.timestamp, frame.data)
+            })
+            .collect::<Vec<_>>();
+        assert_eq!(
+            frames,
+            [(10, vec![1]), (12, vec![2, 3]), (14, vec![4, 5, 6])]
+        );
+    }
+
+    #[test]
+    fn write_frame_at() {
+        let header = read_header_only(&mut &crate::test_util::header(b"VP80", 1)[..]).unwrap();
+        let mut writer = IvfFrameWriter::new(Vec::new(), &header).unwrap();
+
+        let ts = Timestamp::new(2000, Rational64::new(1, 1000));
+        writer.write_frame_at(ts, &[0x50]).unwrap();
+
+        let mut reader = IvfFrameReader::new(Cursor::new(writer.into_inner())).unwrap();
+        let frame = reader.next().unwrap().unwrap();
+        assert_eq!(frame.timestamp, 60);
+        assert_eq!(header.timestamp(frame.timestamp), ts);
+    }
+
+    #[test]
+    fn single_frame() {
+        use av_format::buffer::AccReader;
+        use av_format::demuxer::{Context, Event};
+
+        use crate::demuxer::IvfDemuxer;
+
+        let mut data = Vec::new();
+        write_single_frame(&mut data, Codec::AV1, 640, 480, &[0x12, 0x00, 0x0a, 0x00]).unwrap();
+
+        let mut demuxer = Context::new(IvfDemuxer::new(), AccReader::new(Cursor::new(data)));
+        demuxer.read_headers().unwrap();
+        assert_eq!(demuxer.info.streams[0].duration, Some(1));
+        match demuxer.read_event().unwrap() {
+            Event::NewPacket(pkt) => {
+                assert_eq!(pkt.t.pts, Some(0));
+                assert_eq!(pkt.data, [0x12, 0x00, 0x0a, 0x00]);
+            }
+            event => panic!("unexpected event: {:?}", event),
+        }
+        assert!(matches!(demuxer.read_event().unwrap(), Event::Eof));
+    }
+
+    #[test]
+    fn builder() {
+        use crate::index::read_index;
+
+        let mut builder = IvfBuilder::new(Codec::AV1, 64, 48);
+        builder
+            .set_nframe(7)
+            .set_write_index(true)
+            .frame(3, true, &[0x12, 0x00])
+            .frame(3, false, &[]);
+        let data = builder.build();
+
+        let mut cursor = Cursor::new(&data);
+        let index = read_index(&mut cursor).unwrap().unwrap();
+        assert_eq!(
+            index
+                .iter()
+                .map(|e| (e.offset, e.is_key))
+                .collect::<Vec<_>>(),
+            [(32, true), (46, false)]
+        );
+
+        let reader = IvfFrameReader::new(Cursor::new(&data)).unwrap();
+        assert_eq!(reader.header().nframe, 7);
+        let frames = reader.collect_frames().unwrap();
+        assert_eq!(frames.len(), 2);
+        assert_eq!((frames[1].timestamp, frames[1].size), (3, 0));
+
+        builder.set_keyframe_in_timestamp_msb(true);
+        let data = builder.build();
+        let frame = IvfFrameReader::new(Cursor::new(&data)).unwrap().next();
+        assert_eq!(frame.unwrap().unwrap().timestamp, 3 | 1 << 63);
+    }
+
+    #[test]
+    fn header_bytes() {
+        let header = IvfHeader {
+            version: 0x0102,
+            header_len: 32,
+            width: 0x0304,
+            height: 0x0506,
+            rate: 0x0708_090a,
+            scale: 0x0b0c_0d0e,
+            codec: Codec::VP9,
+            nframe: 0x1112_1314,
+            reserved: 0x1516_1718,
+        };
+
+        #[rustfmt::skip]
+        let expected = [
+            b'D', b'K', b'I', b'F',
+            0x02, 0x01,
+            0x20, 0x00,
+            b'V', b'P', b'9', b'0',
+            0x04, 0x03,
+            0x06, 0x05,
+            0x0a, 0x09, 0x08, 0x07,
+            0x0e, 0x0d, 0x0c, 0x0b,
+            0x14, 0x13, 0x12, 0x11,
+            0x18, 0x17, 0x16, 0x15,
+        ];
+        assert_eq!(header.to_bytes(), expected);
+    }
+
+    #[test]
+    fn frame_header_bytes() {
+        let header = read_header_only(&mut &crate::test_util::header(b"VP80", 1)[..]).unwrap();
+        let mut writer = IvfFrameWriter::new(Vec::new(), &header).unwrap();
+        writer
+            .write_frame(0x0102_0304_0506_0708, &[0xaa; 0x0103])
+            .unwrap();
+
+        let data = writer.into_inner();
+        #[rustfmt::skip]
+        let expected = [
+            0x03, 0x01, 0x00, 0x00,
+            0x08, 0x07, 0x06, 0x05, 0x04, 0x03, 0x02, 0x01,
+        ];
+        assert_eq!(data[32..44], expected);
+        assert_eq!(data.len(), 44 + 0x0103);
+    }
+
+    #[test]
+    fn le_round_trip() {
+        use av_bitstream::byteread::*;
+
+        // xorshift64, enough to cover every byte lane
+        let mut state = 0x9e37_79b9_7f4a_7c15u64;
+        let mut next = || {
+            state ^= state << 13;
+            state ^= state >> 7;
+            state ^= state << 17;
+            state
+        };
+
+        let mut buf = [0u8; 8];
+        for _ in 0..10_000 {
+            let x = next();
+            put_u16l(&mut buf[..2], x as u16);
+            assert_eq!(get_u16l(&buf[..2]), x as u16);
+            assert_eq!(buf[..2], (x as u16).to_le_bytes());
+            put_u32l(&mut buf[..4], x as u32);
+            assert_eq!(get_u32l(&buf[..4]), x as u32);
+            assert_eq!(buf[..4], (x as u32).to_le_bytes());
+            put_u64l(&mut buf, x);
+            assert_eq!(get_u64l(&buf), x);
+            assert_eq!(buf, x.to_le_bytes());
+        }
+    }
+}
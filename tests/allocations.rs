@@ -0,0 +1,91 @@
+use std::alloc::{GlobalAlloc, Layout, System};
+use std::cell::Cell;
+use std::io::Cursor;
+
+use av_format::buffer::AccReader;
+use av_format::demuxer::{Context, Event};
+
+use av_ivf::demuxer::IvfDemuxer;
+use av_ivf::pool::BufferPool;
+
+mod common;
+
+/// Counts every allocation, per thread since the tests run in parallel
+struct Counting;
+
+thread_local! {
+    static ALLOCATIONS: Cell<usize> = const { Cell::new(0) };
+}
+
+fn allocations() -> usize {
+    ALLOCATIONS.with(Cell::get)
+}
+
+unsafe impl GlobalAlloc for Counting {
+    unsafe fn alloc(&self, layout: Layout) -> *mut u8 {
+        let _ = ALLOCATIONS.try_with(|count| count.set(count.get() + 1));
+        System.alloc(layout)
+    }
+
+    unsafe fn dealloc(&self, ptr: *mut u8, layout: Layout) {
+        System.dealloc(ptr, layout)
+    }
+}
+
+#[global_allocator]
+static GLOBAL: Counting = Counting;
+
+const FRAMES: u32 = 300;
+
+/// `Packet::data` is an owned `Vec`, the payload must be copied out of the
+/// read buffer once, but never more than that.
+#[test]
+fn one_allocation_per_packet() {
+    let data = common::vp8(FRAMES, 4096);
+    let mut demuxer = Context::new(IvfDemuxer::new(), AccReader::new(Cursor::new(&data)));
+    demuxer.read_headers().unwrap();
+
+    let before = allocations();
+    let mut packets = 0;
+    while let Event::NewPacket(pkt) = demuxer.read_event().unwrap() {
+        assert_eq!(pkt.data.len(), 4096);
+        packets += 1;
+    }
+    let allocations = allocations() - before;
+
+    assert_eq!(packets, FRAMES);
+    assert!(
+        allocations <= FRAMES as usize + 16,
+        "{} allocations for {} packets",
+        allocations,
+        packets
+    );
+}
+
+/// Recycling the packet buffers removes the per-packet allocation.
+#[test]
+fn pooled_buffers() {
+    let data = common::vp8(FRAMES, 4096);
+    let pool = BufferPool::new(4);
+    let mut ivf_demuxer = IvfDemuxer::new();
+    ivf_demuxer.set_buffer_pool(Some(pool.clone()));
+    let mut demuxer = Context::new(ivf_demuxer, AccReader::new(Cursor::new(&data)));
+    demuxer.read_headers().unwrap();
+
+    let before = allocations();
+    let mut packets = 0;
+    while let Event::NewPacket(pkt) = demuxer.read_event().unwrap() {
+        assert_eq!(pkt.data.len(), 4096);
+        pool.recycle(pkt.data);
+        packets += 1;
+    }
+    let allocations = allocations() - before;
+
+    assert_eq!(packets, FRAMES);
+    assert!(
+        allocations <= 16,
+        "{} allocations for {} packets",
+        allocations,
+        packets
+    );
+}
@@ -0,0 +1,14 @@
+//! Fixtures shared by the integration tests and the benchmarks.
+
+use av_ivf::common::Codec;
+use av_ivf::writer::IvfBuilder;
+
+/// Builds a VP8 stream of `count` frames of `size` bytes each.
+pub fn vp8(count: u32, size: u32) -> Vec<u8> {
+    let mut builder = IvfBuilder::new(Codec::VP8, 352, 288);
+    for i in 0..count {
+        let data = (0..size).map(|b| (b ^ i) as u8).collect::<Vec<_>>();
+        builder.frame(i as u64, false, &data);
+    }
+    builder.build()
+}
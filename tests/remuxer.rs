@@ -12,6 +12,8 @@ use av_format::muxer::{Context as MuxerContext, Writer};
 
 use av_ivf::demuxer::*;
 use av_ivf::muxer::*;
+use av_ivf::reader::read_header_only;
+use av_ivf::validate::payloads_equal;
 
 const IVF: &str = "assets/single_stream_av1.ivf";
 const IVF_OUTPUT: &str = "assets/out_av1.ivf";
@@ -63,6 +65,18 @@ fn demux_mux() {
         .unwrap();
 }
 
+fn check_header() {
+    let original = std::fs::read(IVF).unwrap();
+    let remuxed = std::fs::read(IVF_OUTPUT).unwrap();
+    assert_eq!(original[..32], remuxed[..32]);
+
+    let header_original = read_header_only(&mut &original[..]).unwrap();
+    let header = read_header_only(&mut &remuxed[..]).unwrap();
+    assert!(header_original.eq_exact(&header));
+
+    assert!(payloads_equal(&original[..], &remuxed[..]).unwrap());
+}
+
 fn check_mux() {
     let mut demuxer_original = read_demux(IVF);
     let mut demuxer = read_demux(IVF_OUTPUT);
@@ -110,5 +124,6 @@ fn remuxer() {
     demux_mux();
 
     // Check if the muxed ivf file is equal to the original
+    check_header();
     check_mux();
 }